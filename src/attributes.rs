@@ -0,0 +1,154 @@
+use bitflags::bitflags;
+use std::env;
+
+bitflags! {
+	#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+	pub struct Attributes: u8 {
+		const BOLD = 1 << 0;
+		const DIM = 1 << 1;
+		const ITALIC = 1 << 2;
+		const UNDERLINE = 1 << 3;
+		const BLINK = 1 << 4;
+		const REVERSE = 1 << 5;
+		const STRIKETHROUGH = 1 << 6;
+	}
+}
+
+impl Attributes {
+	// SGR parameters that turn these attributes on
+	pub fn sgr_on(self) -> String {
+		self.sgr(|attr| match attr {
+			Self::BOLD => 1,
+			Self::DIM => 2,
+			Self::ITALIC => 3,
+			Self::UNDERLINE => 4,
+			Self::BLINK => 5,
+			Self::REVERSE => 7,
+			_ => 9,
+		})
+	}
+
+	// SGR parameters that turn these attributes off. Bold and dim share one, so
+	// turning off either turns off both
+	pub fn sgr_off(self) -> String {
+		self.sgr(|attr| match attr {
+			Self::BOLD | Self::DIM => 22,
+			Self::ITALIC => 23,
+			Self::UNDERLINE => 24,
+			Self::BLINK => 25,
+			Self::REVERSE => 27,
+			_ => 29,
+		})
+	}
+
+	fn sgr(self, code: fn(Self) -> u8) -> String {
+		let mut codes: Vec<String> = vec![];
+		for attr in self.iter() {
+			let code = code(attr).to_string();
+			if !codes.contains(&code) {
+				codes.push(code);
+			}
+		}
+		codes.join(";")
+	}
+}
+
+// Only drawn when the cell has Attributes::UNDERLINE
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnderlineStyle {
+	#[default]
+	Single,
+	Double,
+	Curly,
+	Dotted,
+	Dashed,
+}
+
+impl UnderlineStyle {
+	// The sub-parameter for `CSI 4:<n> m`
+	pub fn sgr_code(self) -> u8 {
+		match self {
+			Self::Single => 1,
+			Self::Double => 2,
+			Self::Curly => 3,
+			Self::Dotted => 4,
+			Self::Dashed => 5,
+		}
+	}
+
+	pub fn from_sgr_code(code: u32) -> Option<Self> {
+		match code {
+			1 => Some(Self::Single),
+			2 => Some(Self::Double),
+			3 => Some(Self::Curly),
+			4 => Some(Self::Dotted),
+			5 => Some(Self::Dashed),
+			_ => None,
+		}
+	}
+
+	pub fn supported_from_env() -> bool {
+		let var = |name| env::var(name).ok();
+		Self::detect_support(
+			var("TERM").as_deref(),
+			var("TERM_PROGRAM").as_deref(),
+			var("VTE_VERSION").as_deref(),
+		)
+	}
+
+	// Whether styles other than Single, and underline colors, can be used. Some terminals
+	// show `CSI 4:3 m` as something else entirely, so only ones known to handle it say yes
+	pub fn detect_support(term: Option<&str>, term_program: Option<&str>, vte_version: Option<&str>) -> bool {
+		let term = term.unwrap_or_default();
+		if ["xterm-kitty", "xterm-ghostty", "wezterm", "foot", "contour"]
+			.iter()
+			.any(|prefix| term.starts_with(prefix))
+		{
+			return true;
+		}
+		// Multiplexers only pass them on when set up to
+		if term.starts_with("screen") || term.starts_with("tmux") {
+			return false;
+		}
+		if matches!(term_program, Some("WezTerm" | "ghostty" | "vscode" | "iTerm.app")) {
+			return true;
+		}
+		// VTE has had them since 0.51.2, which reports itself as 5102
+		vte_version
+			.and_then(|version| version.parse::<u32>().ok())
+			.is_some_and(|version| version >= 5102)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn detect_styled_underline_support() {
+		let cases = [
+			(Some("xterm-kitty"), None, None, true),
+			(Some("wezterm"), None, None, true),
+			(Some("foot-extra"), None, None, true),
+			(Some("xterm-256color"), Some("WezTerm"), None, true),
+			(Some("xterm-256color"), Some("vscode"), None, true),
+			(Some("xterm-256color"), None, Some("7600"), true),
+			(Some("xterm-256color"), None, Some("5000"), false),
+			(Some("xterm-256color"), Some("Apple_Terminal"), None, false),
+			(Some("tmux-256color"), Some("ghostty"), None, false),
+			(Some("screen"), None, Some("7600"), false),
+			(Some("linux"), None, None, false),
+			(None, None, None, false),
+		];
+		for (term, term_program, vte_version, supported) in cases {
+			assert_eq!(
+				UnderlineStyle::detect_support(term, term_program, vte_version),
+				supported,
+				"TERM={:?} TERM_PROGRAM={:?} VTE_VERSION={:?}",
+				term,
+				term_program,
+				vte_version
+			);
+		}
+	}
+}
@@ -0,0 +1,368 @@
+use crate::{hyperlink_sequence, Attributes, Color, ColorDepth, Link, Terminal, UnderlineStyle};
+use std::io::{self, Write};
+
+pub type BackendResult = Result<(), io::Error>;
+
+// Something a TerminalCanvas can present its cells to
+pub trait Backend {
+	fn size(&self) -> (u32, u32);
+	fn move_to(&mut self, x: u32, y: u32) -> BackendResult;
+	fn fg_color(&mut self, color: Color) -> BackendResult;
+	fn bg_color(&mut self, color: Color) -> BackendResult;
+	fn underline_color(&mut self, color: Color) -> BackendResult;
+	fn default_underline_color(&mut self) -> BackendResult;
+	fn reset(&mut self) -> BackendResult;
+	fn set_attributes(&mut self, attrs: Attributes) -> BackendResult;
+	fn unset_attributes(&mut self, attrs: Attributes) -> BackendResult;
+	fn underline_style(&mut self, style: UnderlineStyle) -> BackendResult;
+	fn hyperlink(&mut self, link: Option<Link>) -> BackendResult;
+	fn clear(&mut self) -> BackendResult;
+	fn write_char(&mut self, c: char) -> BackendResult;
+	fn write_str(&mut self, s: &str) -> BackendResult;
+	fn flush(&mut self) -> BackendResult;
+}
+
+impl Backend for Terminal {
+	fn size(&self) -> (u32, u32) {
+		Terminal::size(self)
+	}
+
+	fn move_to(&mut self, x: u32, y: u32) -> BackendResult {
+		Terminal::move_to(self, x, y)?;
+		Ok(())
+	}
+
+	fn fg_color(&mut self, color: Color) -> BackendResult {
+		Terminal::fg_color(self, color)?;
+		Ok(())
+	}
+
+	fn bg_color(&mut self, color: Color) -> BackendResult {
+		Terminal::bg_color(self, color)?;
+		Ok(())
+	}
+
+	fn underline_color(&mut self, color: Color) -> BackendResult {
+		Terminal::underline_color(self, color)?;
+		Ok(())
+	}
+
+	fn default_underline_color(&mut self) -> BackendResult {
+		Terminal::default_underline_color(self)?;
+		Ok(())
+	}
+
+	fn reset(&mut self) -> BackendResult {
+		Terminal::reset(self)?;
+		Ok(())
+	}
+
+	fn set_attributes(&mut self, attrs: Attributes) -> BackendResult {
+		Terminal::set_attributes(self, attrs)?;
+		Ok(())
+	}
+
+	fn unset_attributes(&mut self, attrs: Attributes) -> BackendResult {
+		Terminal::unset_attributes(self, attrs)?;
+		Ok(())
+	}
+
+	fn underline_style(&mut self, style: UnderlineStyle) -> BackendResult {
+		Terminal::underline_style(self, style)?;
+		Ok(())
+	}
+
+	fn hyperlink(&mut self, link: Option<Link>) -> BackendResult {
+		Terminal::hyperlink(self, link)?;
+		Ok(())
+	}
+
+	fn clear(&mut self) -> BackendResult {
+		Terminal::clear(self)?;
+		Ok(())
+	}
+
+	fn write_char(&mut self, c: char) -> BackendResult {
+		Terminal::write_char(self, c)?;
+		Ok(())
+	}
+
+	fn write_str(&mut self, s: &str) -> BackendResult {
+		Terminal::write(self, s)?;
+		Ok(())
+	}
+
+	fn flush(&mut self) -> BackendResult {
+		Terminal::flush(self)
+	}
+}
+
+// Writes the same escape sequences as a Terminal, but into any io::Write
+pub struct WriteBackend<W: Write> {
+	writer: W,
+	width: u32,
+	height: u32,
+	color_depth: ColorDepth,
+	styled_underlines: bool,
+}
+
+impl<W: Write> WriteBackend<W> {
+	pub fn new(writer: W, width: u32, height: u32) -> Self {
+		Self {
+			writer,
+			width,
+			height,
+			color_depth: ColorDepth::default(),
+			styled_underlines: true,
+		}
+	}
+
+	pub fn writer(&self) -> &W {
+		&self.writer
+	}
+
+	pub fn writer_mut(&mut self) -> &mut W {
+		&mut self.writer
+	}
+
+	pub fn into_inner(self) -> W {
+		self.writer
+	}
+
+	pub fn resize(&mut self, width: u32, height: u32) {
+		self.width = width;
+		self.height = height;
+	}
+
+	pub fn color_depth(&self) -> ColorDepth {
+		self.color_depth
+	}
+
+	pub fn set_color_depth(&mut self, depth: ColorDepth) {
+		self.color_depth = depth;
+	}
+
+	pub fn styled_underlines(&self) -> bool {
+		self.styled_underlines
+	}
+
+	// Without them every underline is a single one in the fg color, see Terminal::underline_style
+	pub fn set_styled_underlines(&mut self, supported: bool) {
+		self.styled_underlines = supported;
+	}
+
+	fn csi(&mut self, output: &str) -> BackendResult {
+		write!(self.writer, "\x1b[{}", output)
+	}
+
+	fn sgr_color(&mut self, code: u8, color: Color) -> BackendResult {
+		match self.color_depth.sgr_color(code, color) {
+			Some(params) => self.csi(&format!("{}m", params)),
+			None => Ok(()),
+		}
+	}
+}
+
+impl<W: Write> Backend for WriteBackend<W> {
+	fn size(&self) -> (u32, u32) {
+		(self.width, self.height)
+	}
+
+	fn move_to(&mut self, x: u32, y: u32) -> BackendResult {
+		self.csi(&format!("{};{}H", y + 1, x + 1))
+	}
+
+	fn fg_color(&mut self, color: Color) -> BackendResult {
+		self.sgr_color(38, color)
+	}
+
+	fn bg_color(&mut self, color: Color) -> BackendResult {
+		self.sgr_color(48, color)
+	}
+
+	fn underline_color(&mut self, color: Color) -> BackendResult {
+		if !self.styled_underlines {
+			return Ok(());
+		}
+		self.sgr_color(58, color)
+	}
+
+	fn default_underline_color(&mut self) -> BackendResult {
+		if !self.styled_underlines {
+			return Ok(());
+		}
+		self.csi("59m")
+	}
+
+	fn reset(&mut self) -> BackendResult {
+		self.csi("m")
+	}
+
+	fn set_attributes(&mut self, attrs: Attributes) -> BackendResult {
+		if attrs.is_empty() {
+			return Ok(());
+		}
+		self.csi(&format!("{}m", attrs.sgr_on()))
+	}
+
+	fn unset_attributes(&mut self, attrs: Attributes) -> BackendResult {
+		if attrs.is_empty() {
+			return Ok(());
+		}
+		self.csi(&format!("{}m", attrs.sgr_off()))
+	}
+
+	fn underline_style(&mut self, style: UnderlineStyle) -> BackendResult {
+		if !self.styled_underlines {
+			return self.csi("4m");
+		}
+		self.csi(&format!("4:{}m", style.sgr_code()))
+	}
+
+	fn hyperlink(&mut self, link: Option<Link>) -> BackendResult {
+		self.writer.write_all(hyperlink_sequence(link).as_bytes())
+	}
+
+	fn clear(&mut self) -> BackendResult {
+		self.reset()?;
+		self.csi("2J")
+	}
+
+	fn write_char(&mut self, c: char) -> BackendResult {
+		let mut bytes = [0; 4];
+		self.writer.write_all(c.encode_utf8(&mut bytes).as_bytes())
+	}
+
+	fn write_str(&mut self, s: &str) -> BackendResult {
+		self.writer.write_all(s.as_bytes())
+	}
+
+	fn flush(&mut self) -> BackendResult {
+		self.writer.flush()
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BackendCommand {
+	MoveTo(u32, u32),
+	FgColor(Color),
+	BgColor(Color),
+	UnderlineColor(Color),
+	DefaultUnderlineColor,
+	Reset,
+	SetAttributes(Attributes),
+	UnsetAttributes(Attributes),
+	UnderlineStyle(UnderlineStyle),
+	Hyperlink(Option<Link>),
+	Clear,
+	WriteChar(char),
+	WriteStr(String),
+	Flush,
+}
+
+// Records every command it receives instead of drawing anything
+#[derive(Debug, Default, Clone)]
+pub struct MemoryBackend {
+	width: u32,
+	height: u32,
+	commands: Vec<BackendCommand>,
+}
+
+impl MemoryBackend {
+	pub fn new(width: u32, height: u32) -> Self {
+		Self {
+			width,
+			height,
+			commands: vec![],
+		}
+	}
+
+	pub fn commands(&self) -> &[BackendCommand] {
+		&self.commands
+	}
+
+	pub fn take_commands(&mut self) -> Vec<BackendCommand> {
+		std::mem::take(&mut self.commands)
+	}
+
+	pub fn resize(&mut self, width: u32, height: u32) {
+		self.width = width;
+		self.height = height;
+	}
+}
+
+impl Backend for MemoryBackend {
+	fn size(&self) -> (u32, u32) {
+		(self.width, self.height)
+	}
+
+	fn move_to(&mut self, x: u32, y: u32) -> BackendResult {
+		self.commands.push(BackendCommand::MoveTo(x, y));
+		Ok(())
+	}
+
+	fn fg_color(&mut self, color: Color) -> BackendResult {
+		self.commands.push(BackendCommand::FgColor(color));
+		Ok(())
+	}
+
+	fn bg_color(&mut self, color: Color) -> BackendResult {
+		self.commands.push(BackendCommand::BgColor(color));
+		Ok(())
+	}
+
+	fn underline_color(&mut self, color: Color) -> BackendResult {
+		self.commands.push(BackendCommand::UnderlineColor(color));
+		Ok(())
+	}
+
+	fn default_underline_color(&mut self) -> BackendResult {
+		self.commands.push(BackendCommand::DefaultUnderlineColor);
+		Ok(())
+	}
+
+	fn reset(&mut self) -> BackendResult {
+		self.commands.push(BackendCommand::Reset);
+		Ok(())
+	}
+
+	fn set_attributes(&mut self, attrs: Attributes) -> BackendResult {
+		self.commands.push(BackendCommand::SetAttributes(attrs));
+		Ok(())
+	}
+
+	fn unset_attributes(&mut self, attrs: Attributes) -> BackendResult {
+		self.commands.push(BackendCommand::UnsetAttributes(attrs));
+		Ok(())
+	}
+
+	fn underline_style(&mut self, style: UnderlineStyle) -> BackendResult {
+		self.commands.push(BackendCommand::UnderlineStyle(style));
+		Ok(())
+	}
+
+	fn hyperlink(&mut self, link: Option<Link>) -> BackendResult {
+		self.commands.push(BackendCommand::Hyperlink(link));
+		Ok(())
+	}
+
+	fn clear(&mut self) -> BackendResult {
+		self.commands.push(BackendCommand::Clear);
+		Ok(())
+	}
+
+	fn write_char(&mut self, c: char) -> BackendResult {
+		self.commands.push(BackendCommand::WriteChar(c));
+		Ok(())
+	}
+
+	fn write_str(&mut self, s: &str) -> BackendResult {
+		self.commands.push(BackendCommand::WriteStr(s.to_string()));
+		Ok(())
+	}
+
+	fn flush(&mut self) -> BackendResult {
+		self.commands.push(BackendCommand::Flush);
+		Ok(())
+	}
+}
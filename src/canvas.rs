@@ -1,4 +1,5 @@
-use crate::{geom::Rect, Cell, Color};
+use crate::{geom::Rect, Cell, Color, Symbol};
+use unicode_segmentation::UnicodeSegmentation;
 
 #[derive(Default, Clone, Debug)]
 pub struct Canvas {
@@ -13,7 +14,7 @@ pub struct CanvasRegion<'a> {
 }
 
 impl<'a> CanvasRegion<'a> {
-	pub fn region_mut(&mut self, rect: Rect) -> CanvasRegion {
+	pub fn region_mut(&mut self, rect: Rect) -> CanvasRegion<'_> {
 		CanvasRegion {
 			canvas: self.canvas,
 			rect: Rect::new(rect.x + self.rect.x, rect.y + self.rect.y, rect.width, rect.height),
@@ -55,11 +56,11 @@ impl Canvas {
 		canvas
 	}
 
-	pub fn region_mut(&mut self, rect: Rect) -> CanvasRegion {
+	pub fn region_mut(&mut self, rect: Rect) -> CanvasRegion<'_> {
 		CanvasRegion { canvas: self, rect }
 	}
 
-	pub fn as_region_mut(&mut self) -> CanvasRegion {
+	pub fn as_region_mut(&mut self) -> CanvasRegion<'_> {
 		self.region_mut(Rect::new(0, 0, self.width as i32, self.height as i32))
 	}
 
@@ -125,13 +126,46 @@ impl Canvas {
 		}
 	}
 
-	pub fn draw_text(&mut self, mut x: i32, y: i32, fg: Color, bg: Color, text: &str) {
-		for symbol in text.chars() {
-			if let Some(dst) = self.cell_mut(x, y) {
-				let cell = Cell { fg, bg, symbol };
-				*dst = cell.blend(dst);
+	pub fn draw_text(&mut self, x: i32, y: i32, fg: Color, bg: Color, text: &str) {
+		let style = Cell { fg, bg, ..Cell::null() };
+		self.draw_styled_text(x, y, &style, text);
+	}
+
+	// Draw text with the colors and attributes of `style`, its symbol is ignored
+	pub fn draw_styled_text(&mut self, mut x: i32, y: i32, style: &Cell, text: &str) {
+		for grapheme in text.graphemes(true) {
+			let mut symbol = Symbol::new(grapheme);
+			let width = symbol.width().max(1) as i32;
+			if width == 2 && x + 1 == self.width as i32 {
+				// Wide glyph doesn't fit on the end of the line
+				symbol = Symbol::Char(' ');
+			}
+			self.blend_cell(
+				x,
+				y,
+				Cell {
+					symbol,
+					..style.clone()
+				},
+			);
+			if width == 2 {
+				let symbol = Symbol::Continuation;
+				self.blend_cell(
+					x + 1,
+					y,
+					Cell {
+						symbol,
+						..style.clone()
+					},
+				);
 			}
-			x += 1;
+			x += width;
+		}
+	}
+
+	fn blend_cell(&mut self, x: i32, y: i32, cell: Cell) {
+		if let Some(dst) = self.cell_mut(x, y) {
+			*dst = cell.blend(dst);
 		}
 	}
 
@@ -1,4 +1,4 @@
-use crate::Color;
+use crate::{Attributes, Color, Link, Symbol, UnderlineStyle};
 use phf::phf_map;
 
 // Assign 1 bit per each of the 4 sides of a char
@@ -61,27 +61,36 @@ fn get_char(edges: u8, default: char) -> char {
 	*EDGES_TO_CHAR.get(&edges).unwrap_or(&default)
 }
 
-fn blend_symbol(front: char, back: char) -> char {
-	if front == ' ' || (front == '\0' && back != '\0') {
-		return back;
+fn blend_symbol(front: &Symbol, back: &Symbol) -> Symbol {
+	if *front == ' ' || (*front == '\0' && *back != '\0') {
+		return back.clone();
 	}
-	let c0 = get_edges(front);
+	// Only box drawing chars are merged
+	let (Some(front_char), Some(back_char)) = (front.as_char(), back.as_char()) else {
+		return front.clone();
+	};
+	let c0 = get_edges(front_char);
 	if c0 == 0 {
-		return front;
+		return front.clone();
 	}
-	let c1 = get_edges(back);
+	let c1 = get_edges(back_char);
 	if c1 == 0 {
-		return front;
+		return front.clone();
 	}
 
-	get_char(c0 | c1, front)
+	Symbol::Char(get_char(c0 | c1, front_char))
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct Cell {
 	pub bg: Color,
 	pub fg: Color,
-	pub symbol: char,
+	pub symbol: Symbol,
+	pub attrs: Attributes,
+	pub underline_style: UnderlineStyle,
+	// Defaults to the fg color
+	pub underline_color: Option<Color>,
+	pub link: Option<Link>,
 }
 
 impl Cell {
@@ -89,7 +98,11 @@ impl Cell {
 		Self {
 			fg: Color { r: 0, g: 0, b: 0, a: 0 },
 			bg: Color { r: 0, g: 0, b: 0, a: 0 },
-			symbol: '\0',
+			symbol: Symbol::Char('\0'),
+			attrs: Attributes::empty(),
+			underline_style: UnderlineStyle::Single,
+			underline_color: None,
+			link: None,
 		}
 	}
 
@@ -99,20 +112,36 @@ impl Cell {
 
 	pub fn blend(&self, cell: &Cell) -> Cell {
 		let bg = self.bg.blend(&cell.bg);
-		let mut fg = self.fg.clone();
-		let mut symbol = self.symbol;
+		let mut fg = self.fg;
+		let mut symbol = self.symbol.clone();
+		let mut attrs = self.attrs;
+		let mut underline_style = self.underline_style;
+		let mut underline_color = self.underline_color;
+		let mut link = self.link;
 
 		if (self.symbol == ' ' || self.symbol == '\0') && cell.symbol != '\0' {
 			fg = self.bg.blend(&cell.fg);
-			symbol = cell.symbol
+			symbol = cell.symbol.clone();
+			attrs = cell.attrs;
+			underline_style = cell.underline_style;
+			underline_color = cell.underline_color;
+			link = cell.link;
 		} else if self.bg.a < 250 {
 			// If semi-transparent, blend symbols
 			fg = fg.blend(&bg);
-			symbol = blend_symbol(self.symbol, cell.symbol)
+			symbol = blend_symbol(&self.symbol, &cell.symbol)
 		} else {
 			fg = fg.blend(&bg);
 		};
 
-		Cell { fg, bg, symbol }
+		Cell {
+			fg,
+			bg,
+			symbol,
+			attrs,
+			underline_style,
+			underline_color,
+			link,
+		}
 	}
 }
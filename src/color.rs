@@ -1,7 +1,100 @@
+use std::env;
 use std::mem;
 
 pub type Ansi8BitColor = u8;
 
+// xterm's default palette for the first 16 ANSI colors
+const ANSI_16_COLORS: [(u8, u8, u8); 16] = [
+	(0, 0, 0),
+	(205, 0, 0),
+	(0, 205, 0),
+	(205, 205, 0),
+	(0, 0, 238),
+	(205, 0, 205),
+	(0, 205, 205),
+	(229, 229, 229),
+	(127, 127, 127),
+	(255, 0, 0),
+	(0, 255, 0),
+	(255, 255, 0),
+	(92, 92, 255),
+	(255, 0, 255),
+	(0, 255, 255),
+	(255, 255, 255),
+];
+
+// How many colors a terminal can show, from least to most
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ColorDepth {
+	Mono,
+	Ansi8,
+	Ansi16,
+	Ansi256,
+	#[default]
+	TrueColor,
+}
+
+impl ColorDepth {
+	pub fn from_env() -> Self {
+		let colorterm = env::var("COLORTERM").ok();
+		let term = env::var("TERM").ok();
+		Self::detect(colorterm.as_deref(), term.as_deref())
+	}
+
+	// There's no reliable way to ask, so this guesses from the usual variables
+	pub fn detect(colorterm: Option<&str>, term: Option<&str>) -> Self {
+		if matches!(colorterm, Some("truecolor" | "24bit")) {
+			return ColorDepth::TrueColor;
+		}
+		let term = match term {
+			Some(term) => term,
+			None => return ColorDepth::Mono,
+		};
+		if term.ends_with("-direct") || term.contains("truecolor") || term.contains("24bit") {
+			return ColorDepth::TrueColor;
+		}
+		// These set TERM to something generic, but all support 24-bit color
+		if ["xterm-kitty", "alacritty", "wezterm", "xterm-ghostty", "foot"].contains(&term) {
+			return ColorDepth::TrueColor;
+		}
+		if term.contains("256color") {
+			return ColorDepth::Ansi256;
+		}
+		if term == "dumb" || term.starts_with("vt1") || term.starts_with("vt2") {
+			return ColorDepth::Mono;
+		}
+		// Bright colors work here, screen and the Linux console only have 8
+		if ["xterm", "rxvt", "konsole", "putty", "gnome", "iterm"]
+			.iter()
+			.any(|prefix| term.starts_with(prefix))
+		{
+			return ColorDepth::Ansi16;
+		}
+		ColorDepth::Ansi8
+	}
+
+	// SGR parameters to set `color` for `code` (38 fg, 48 bg or 58 underline),
+	// or None if it can't be shown at this depth
+	pub(crate) fn sgr_color(&self, code: u8, color: Color) -> Option<String> {
+		match self {
+			ColorDepth::TrueColor => {
+				let (r, g, b) = color.as_rgb();
+				Some(format!("{};2;{};{};{}", code, r, g, b))
+			}
+			ColorDepth::Ansi256 => Some(format!("{};5;{}", code, color.as_8bit_ansi())),
+			// There are no short codes for underline colors
+			_ if code == 58 => None,
+			ColorDepth::Ansi16 => match color.as_4bit_ansi() {
+				// 30-37 and 40-47 for the normal colors, 90-97 and 100-107 for bright ones
+				n @ 0..=7 => Some(format!("{}", code - 8 + n)),
+				n => Some(format!("{}", code + 52 + n - 8)),
+			},
+			ColorDepth::Ansi8 => Some(format!("{}", code - 8 + color.as_3bit_ansi())),
+			ColorDepth::Mono => None,
+		}
+	}
+}
+
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
 pub struct Color {
@@ -44,7 +137,7 @@ impl From<String> for Color {
 
 impl From<&str> for Color {
 	fn from(mut hex: &str) -> Color {
-		if hex.len() == 0 {
+		if hex.is_empty() {
 			return Color::black();
 		}
 		if hex.chars().nth(0).unwrap() == '#' {
@@ -210,6 +303,23 @@ impl Color {
 		}
 	}
 
+	pub fn from_8bit_ansi(code: Ansi8BitColor) -> Self {
+		match code {
+			0..=15 => {
+				let (r, g, b) = ANSI_16_COLORS[code as usize];
+				Color::rgb(r, g, b)
+			}
+			16..=231 => {
+				let code = code - 16;
+				let r = code / 36;
+				let g = (code / 6) % 6;
+				let b = code % 6;
+				Color::rgb(r * 51, g * 51, b * 51)
+			}
+			_ => Color::grey(8 + (code - 232) * 10),
+		}
+	}
+
 	pub fn as_8bit_ansi(&self) -> Ansi8BitColor {
 		let r = self.r / 51;
 		let g = self.g / 51;
@@ -218,6 +328,28 @@ impl Color {
 		16 + 36 * r + 6 * g + b
 	}
 
+	// Nearest of the 16 basic colors, using xterm's palette
+	pub fn as_4bit_ansi(&self) -> Ansi8BitColor {
+		self.nearest_ansi(&ANSI_16_COLORS)
+	}
+
+	// Nearest of the 8 colors without the bright variants
+	pub fn as_3bit_ansi(&self) -> Ansi8BitColor {
+		self.nearest_ansi(&ANSI_16_COLORS[..8])
+	}
+
+	fn nearest_ansi(&self, palette: &[(u8, u8, u8)]) -> Ansi8BitColor {
+		let distance = |&(r, g, b): &(u8, u8, u8)| {
+			let dr = self.r as i32 - r as i32;
+			let dg = self.g as i32 - g as i32;
+			let db = self.b as i32 - b as i32;
+			// Weighted roughly by how sensitive eyes are to each channel
+			2 * dr * dr + 4 * dg * dg + 3 * db * db
+		};
+		let nearest = palette.iter().enumerate().min_by_key(|(_, rgb)| distance(rgb));
+		nearest.map_or(0, |(code, _)| code as Ansi8BitColor)
+	}
+
 	pub fn as_rgb(&self) -> (u8, u8, u8) {
 		(self.r, self.g, self.b)
 	}
@@ -271,7 +403,7 @@ impl Color {
 
 	pub fn blend(&self, bg: &Color) -> Color {
 		if self.a == 0xff {
-			return self.clone();
+			return *self;
 		}
 		let (fg_r, fg_g, fg_b, fg_a) = self.as_floats();
 		let (bg_r, bg_g, bg_b, bg_a) = bg.as_floats();
@@ -316,5 +448,83 @@ fn hue_to_rgb(p: f32, q: f32, mut t: f32) -> f32 {
 		return p + (q - p) * (2.0 / 3.0 - t) * 6.0;
 	}
 
-	return p;
+	p
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn detect() {
+		use ColorDepth::*;
+		let cases = [
+			(None, None, Mono),
+			(None, Some("dumb"), Mono),
+			(None, Some("vt100"), Mono),
+			(None, Some("screen"), Ansi8),
+			(None, Some("linux"), Ansi8),
+			(None, Some("xterm"), Ansi16),
+			(None, Some("rxvt-unicode"), Ansi16),
+			(None, Some("xterm-256color"), Ansi256),
+			(None, Some("screen-256color"), Ansi256),
+			(None, Some("xterm-direct"), TrueColor),
+			(None, Some("xterm-kitty"), TrueColor),
+			(Some("truecolor"), Some("xterm-256color"), TrueColor),
+			(Some("24bit"), Some("screen"), TrueColor),
+			(Some("24bit"), None, TrueColor),
+			// Anything else in COLORTERM says nothing about the depth
+			(Some("yes"), Some("xterm-256color"), Ansi256),
+			(Some("yes"), None, Mono),
+		];
+		for (colorterm, term, depth) in cases {
+			assert_eq!(
+				ColorDepth::detect(colorterm, term),
+				depth,
+				"COLORTERM={:?} TERM={:?}",
+				colorterm,
+				term
+			);
+		}
+	}
+
+	#[test]
+	fn sgr_color() {
+		use ColorDepth::*;
+		let red = Color::rgb(205, 0, 0);
+		let bright_red = Color::rgb(255, 0, 0);
+		let white = Color::rgb(255, 255, 255);
+		let cases = [
+			(TrueColor, 38, bright_red, Some("38;2;255;0;0")),
+			(TrueColor, 58, red, Some("58;2;205;0;0")),
+			(Ansi256, 38, bright_red, Some("38;5;196")),
+			(Ansi256, 48, white, Some("48;5;231")),
+			(Ansi256, 58, red, Some("58;5;160")),
+			// 30-37 and 40-47, then 90-97 and 100-107 for the bright ones
+			(Ansi16, 38, red, Some("31")),
+			(Ansi16, 48, red, Some("41")),
+			(Ansi16, 38, bright_red, Some("91")),
+			(Ansi16, 48, bright_red, Some("101")),
+			(Ansi16, 38, white, Some("97")),
+			(Ansi16, 48, Color::rgb(0, 0, 0), Some("40")),
+			(Ansi16, 58, red, None),
+			// Bright colors become their normal ones
+			(Ansi8, 38, bright_red, Some("31")),
+			(Ansi8, 48, white, Some("47")),
+			(Ansi8, 58, red, None),
+			(Mono, 38, red, None),
+			(Mono, 48, white, None),
+			(Mono, 58, red, None),
+		];
+		for (depth, code, color, params) in cases {
+			assert_eq!(
+				depth.sgr_color(code, color).as_deref(),
+				params,
+				"{:?} {} {:?}",
+				depth,
+				code,
+				color
+			);
+		}
+	}
 }
@@ -1,3 +1,31 @@
+use bitflags::bitflags;
+
+bitflags! {
+	#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+	pub struct Modifiers: u8 {
+		const SHIFT = 1 << 0;
+		const ALT = 1 << 1;
+		const CTRL = 1 << 2;
+		const SUPER = 1 << 3;
+		const HYPER = 1 << 4;
+		const META = 1 << 5;
+		const CAPS_LOCK = 1 << 6;
+		const NUM_LOCK = 1 << 7;
+	}
+}
+
+bitflags! {
+	// Progressive enhancements for the kitty keyboard protocol
+	#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+	pub struct KittyKeyboardFlags: u8 {
+		const DISAMBIGUATE_ESCAPE_CODES = 1 << 0;
+		const REPORT_EVENT_TYPES = 1 << 1;
+		const REPORT_ALTERNATE_KEYS = 1 << 2;
+		const REPORT_ALL_KEYS_AS_ESCAPE_CODES = 1 << 3;
+		const REPORT_ASSOCIATED_TEXT = 1 << 4;
+	}
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum MouseButton {
 	Left = 1 << 0,
@@ -11,14 +39,67 @@ pub enum MouseButton {
 	Unknown = 1 << 8,
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MouseProtocol {
+	// Single byte coords, limited to 223 columns and rows
+	X10,
+	// Decimal coords, but releases don't say which button
+	Urxvt,
+	// Decimal coords with distinct press and release
+	#[default]
+	Sgr,
+}
+
+// What to do when the user presses ctrl+c while in raw mode
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptPolicy {
+	// Send Event::Interrupt and let the app decide how to quit
+	#[default]
+	Event,
+	// Send it as a regular ctrl+c key press
+	KeyPress,
+	// Raise SIGINT as usual, which kills the process without restoring the terminal
+	Signal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Key {
+	// Presses of text keys are sent as KeyPress, this is for repeats and releases
+	Char(char),
+	Up,
+	Down,
+	Left,
+	Right,
+	F(u8),
+	Home,
+	End,
+	Insert,
+	Delete,
+	PageUp,
+	PageDown,
+	Tab,
+	BackTab,
+	Enter,
+	Backspace,
+	Esc,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Event {
 	Resize(u32, u32),
 	PixelResize(u32, u32),
 	FontResize(u32, u32),
-	MouseMove(MouseButton, u32, u32),
-	MouseUp(MouseButton, u32, u32),
-	MouseDown(MouseButton, u32, u32),
-	KeyPress(char),
+	MouseMove(MouseButton, u32, u32, Modifiers),
+	MouseUp(MouseButton, u32, u32, Modifiers),
+	MouseDown(MouseButton, u32, u32, Modifiers),
+	KeyPress(char, Modifiers),
+	Key(Key, Modifiers),
+	KeyRepeat(Key, Modifiers),
+	KeyRelease(Key, Modifiers),
+	Paste(String),
+	FocusGained,
+	FocusLost,
+	Interrupt,
+	Resumed,
 	Unknown,
 }
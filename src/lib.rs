@@ -1,9 +1,15 @@
+mod attributes;
+pub use attributes::*;
 mod cell;
 pub use cell::*;
 mod color;
 pub use color::*;
 mod event;
 pub use event::*;
+mod link;
+pub use link::*;
+mod symbol;
+pub use symbol::*;
 #[cfg(not(target_arch = "wasm32"))]
 mod terminal;
 #[cfg(target_arch = "wasm32")]
@@ -12,80 +18,200 @@ mod terminal;
 pub use terminal::*;
 mod canvas;
 pub use canvas::*;
+mod backend;
+pub use backend::*;
+mod virtual_terminal;
+pub use virtual_terminal::*;
 pub mod geom;
 
 use std::io;
-use std::sync::mpsc::{Receiver, TryRecvError};
+use std::iter;
+use std::sync::mpsc::{Receiver, RecvError, RecvTimeoutError, TryRecvError};
+use std::time::{Duration, Instant};
 
-pub struct TerminalCanvas {
-	term: Terminal,
+pub struct TerminalCanvas<B: Backend = Terminal> {
+	term: B,
 	front_buffer: Canvas,
 	back_buffer: Canvas,
-	event_rx: Receiver<Event>,
+	event_rx: Option<Receiver<Event>>,
+	resume_error: Option<io::Error>,
+}
+
+impl Default for TerminalCanvas {
+	fn default() -> Self {
+		Self::new()
+	}
 }
 
 impl TerminalCanvas {
 	pub fn new() -> Self {
-		let mut term = Terminal::new();
-		let event_rx = term.event_channel();
-		let front_buffer = Canvas::new(term.width(), term.height());
-		let back_buffer = Canvas::new(term.width(), term.height());
-		Self {
-			term,
-			front_buffer,
-			back_buffer,
-			event_rx,
-		}
+		Self::with_terminal(Terminal::new())
 	}
 
-	pub fn canvas(&self) -> &Canvas {
-		&self.back_buffer
+	pub fn with_terminal(mut term: Terminal) -> Self {
+		let event_rx = term.event_channel();
+		let mut canvas = Self::with_backend(term);
+		canvas.event_rx = Some(event_rx);
+		canvas
 	}
 
-	pub fn canvas_mut(&mut self) -> &mut Canvas {
-		&mut self.back_buffer
-	}
+	pub fn attach(&mut self) -> Result<TerminalGuard, io::Error> {
+		self.term.listen_for_resize()?;
+		self.term.listen_for_suspend()?;
+		self.enter()?;
+		self.term.listen_for_events()?;
 
-	pub fn width(&self) -> u32 {
-		self.term.width()
+		Ok(self.term.guard())
 	}
 
-	pub fn height(&self) -> u32 {
-		self.term.height()
-	}
+	// Like attach, but input and resizes are left for read_events or an EventStream
+	// instead of being read on background threads
+	pub fn attach_polling(&mut self) -> Result<TerminalGuard, io::Error> {
+		self.term.start_polling()?;
+		self.term.refresh_size();
+		self.enter()?;
 
-	pub fn next_event(&mut self) -> Result<Event, TryRecvError> {
-		self.event_rx.try_recv()
+		Ok(self.term.guard())
 	}
 
-	pub fn attach(&mut self) -> Result<(), io::Error> {
+	// Set up the screen, both when attaching and when resuming after Ctrl+Z
+	fn enter(&mut self) -> Result<(), io::Error> {
 		let term = &mut self.term;
-		term.listen_for_resize();
+		// Kitty keyboard flags popped by Ctrl+Z go back on the screen they were pushed on
+		term.resume_kitty_keyboard()?;
 		term.alt_screen()?;
+		term.resume_kitty_keyboard()?;
 		term.hide_cursor()?;
 		term.raw_mode();
 		term.enable_mouse()?;
 		term.enable_mouse_move()?;
-		term.flush()?;
-		term.listen_for_events();
+		term.enable_bracketed_paste()?;
+		term.enable_focus_reporting()?;
 		term.clear()?;
 		term.move_to(0, 0)?;
+		term.flush()?;
+
+		// The screen is blank now, so the next present needs to draw everything
+		self.front_buffer.clear();
 
 		Ok(())
 	}
 
 	pub fn detach(&mut self) -> Result<(), io::Error> {
 		let term = &mut self.term;
+		term.stop_listening();
+		// The alt and main screens each have their own kitty keyboard flags
+		term.reset_kitty_keyboard()?;
 		term.main_screen()?;
+		term.reset_kitty_keyboard()?;
 		term.show_cursor()?;
 		term.normal_mode();
 		term.disable_mouse_move()?;
 		term.disable_mouse()?;
+		term.disable_bracketed_paste()?;
+		term.disable_focus_reporting()?;
 		term.flush()?;
 
 		Ok(())
 	}
 
+	pub fn next_event(&mut self) -> Result<Event, TryRecvError> {
+		let event = match &self.event_rx {
+			Some(rx) => rx.try_recv()?,
+			None => return Err(TryRecvError::Disconnected),
+		};
+		Ok(self.handle_event(event))
+	}
+
+	pub fn wait_event(&mut self) -> Result<Event, RecvError> {
+		let event = match &self.event_rx {
+			Some(rx) => rx.recv()?,
+			None => return Err(RecvError),
+		};
+		Ok(self.handle_event(event))
+	}
+
+	pub fn wait_event_timeout(&mut self, timeout: Duration) -> Result<Event, RecvTimeoutError> {
+		let event = match &self.event_rx {
+			Some(rx) => rx.recv_timeout(timeout)?,
+			None => return Err(RecvTimeoutError::Disconnected),
+		};
+		Ok(self.handle_event(event))
+	}
+
+	// Every event that has already arrived, without blocking
+	pub fn pending_events(&mut self) -> impl Iterator<Item = Event> + '_ {
+		iter::from_fn(move || self.next_event().ok())
+	}
+
+	// Everything that's happened since the last call, for use after attach_polling
+	pub fn read_events(&mut self) -> Result<Vec<Event>, io::Error> {
+		let events = self.term.read_events()?;
+		Ok(events.into_iter().map(|event| self.handle_event(event)).collect())
+	}
+
+	// Wait no later than this before calling read_events again, so a lone ESC becomes the Esc key
+	pub fn escape_deadline(&self) -> Option<Instant> {
+		self.term.escape_deadline()
+	}
+
+	// Why the screen couldn't be set up again after the last Resumed event, if it couldn't
+	pub fn take_resume_error(&mut self) -> Option<io::Error> {
+		self.resume_error.take()
+	}
+
+	// For use after attach_polling, with everything it yields passed through handle_event
+	#[cfg(all(feature = "async", not(target_arch = "wasm32")))]
+	pub fn event_stream(&mut self) -> Result<EventStream, io::Error> {
+		self.term.event_stream()
+	}
+
+	// Sets the screen up again after Resumed. The other ways of reading events already
+	// do this, it's only needed for events from an EventStream
+	pub fn handle_event(&mut self, event: Event) -> Event {
+		if event == Event::Resumed {
+			self.resume_error = self.enter().err();
+		}
+		event
+	}
+}
+
+impl<B: Backend> TerminalCanvas<B> {
+	pub fn with_backend(backend: B) -> Self {
+		let (w, h) = backend.size();
+		Self {
+			term: backend,
+			front_buffer: Canvas::new(w, h),
+			back_buffer: Canvas::new(w, h),
+			event_rx: None,
+			resume_error: None,
+		}
+	}
+
+	pub fn backend(&self) -> &B {
+		&self.term
+	}
+
+	pub fn backend_mut(&mut self) -> &mut B {
+		&mut self.term
+	}
+
+	pub fn canvas(&self) -> &Canvas {
+		&self.back_buffer
+	}
+
+	pub fn canvas_mut(&mut self) -> &mut Canvas {
+		&mut self.back_buffer
+	}
+
+	pub fn width(&self) -> u32 {
+		self.term.size().0
+	}
+
+	pub fn height(&self) -> u32 {
+		self.term.size().1
+	}
+
 	pub fn set_cell(&mut self, x: i32, y: i32, cell: Cell) {
 		if let Some(dst) = self.canvas_mut().cell_mut(x, y) {
 			*dst = cell;
@@ -96,17 +222,18 @@ impl TerminalCanvas {
 		self.canvas_mut().clear();
 	}
 
-	pub fn refresh_size(&mut self) {
+	pub fn refresh_size(&mut self) -> Result<(), io::Error> {
 		let (w, h) = self.term.size();
 		if w != self.back_buffer.width() || h != self.back_buffer.height() {
 			self.back_buffer.resize(w, h);
 			self.front_buffer.resize(w, h);
-			self.term.clear().unwrap();
+			self.term.clear()?;
 		}
+		Ok(())
 	}
 
 	pub fn present(&mut self) -> Result<(), io::Error> {
-		self.refresh_size();
+		self.refresh_size()?;
 		let front = &mut self.front_buffer;
 		let back = &mut self.back_buffer;
 		let term = &mut self.term;
@@ -114,12 +241,23 @@ impl TerminalCanvas {
 		let (w, h) = front.size();
 		let (mut cursor_x, mut cursor_y) = (-1, -1);
 		let (mut cursor_fg, mut cursor_bg) = (Color::transparent(), Color::transparent());
+		let mut cursor_attrs = Attributes::empty();
+		let mut cursor_underline_style = UnderlineStyle::Single;
+		let mut cursor_underline_color = None;
+		let mut cursor_link = None;
 		term.reset()?;
 		for y in 0..h as i32 {
+			let dirty = dirty_cells(front, back, y);
 			for x in 0..w as i32 {
+				if !dirty[x as usize] {
+					continue;
+				}
 				if let Some(back_cell) = back.cell(x, y) {
 					if let Some(front_cell) = front.cell_mut(x, y) {
-						if back_cell == front_cell {
+						*front_cell = back_cell.clone();
+
+						if front_cell.symbol.is_continuation() && cursor_x == x + 1 && cursor_y == y {
+							// Already drawn as part of the wide glyph to the left
 							continue;
 						}
 
@@ -129,20 +267,36 @@ impl TerminalCanvas {
 							term.move_to(x as u32, y as u32)?;
 						}
 
-						*front_cell = back_cell.clone();
+						// Contiguous cells with the same link are opened and closed as one run
+						let link = if front_cell.symbol == '\0' {
+							None
+						} else {
+							front_cell.link
+						};
+						if cursor_link != link {
+							cursor_link = link;
+							term.hyperlink(link)?;
+						}
 
 						if front_cell.symbol == '\0' {
-							if cursor_fg != Color::transparent() || cursor_bg != Color::transparent() {
+							if cursor_fg != Color::transparent()
+								|| cursor_bg != Color::transparent()
+								|| !cursor_attrs.is_empty()
+								|| cursor_underline_color.is_some()
+							{
 								term.reset()?;
 								cursor_fg = Color::transparent();
 								cursor_bg = Color::transparent();
+								cursor_attrs = Attributes::empty();
+								cursor_underline_color = None;
 							}
 							term.write_char(' ')?;
+							cursor_x += 1;
 						} else {
-							let fg = front_cell.fg.clone();
+							let fg = front_cell.fg;
 							let bg = {
 								if front_cell.bg.a > 0 {
-									front_cell.bg.clone()
+									front_cell.bg
 								} else {
 									Color::transparent()
 								}
@@ -153,6 +307,8 @@ impl TerminalCanvas {
 								if bg == Color::transparent() {
 									term.reset()?;
 									cursor_fg = Color::transparent();
+									cursor_attrs = Attributes::empty();
+									cursor_underline_color = None;
 								} else {
 									term.bg_color(front_cell.bg)?;
 								}
@@ -163,14 +319,60 @@ impl TerminalCanvas {
 								term.fg_color(front_cell.fg)?;
 							}
 
-							term.write_char(front_cell.symbol)?;
+							let attrs = front_cell.attrs;
+							let underline_style = front_cell.underline_style;
+							let restyle_underline =
+								attrs.contains(Attributes::UNDERLINE) && cursor_underline_style != underline_style;
+							if cursor_attrs != attrs || restyle_underline {
+								let removed = cursor_attrs - attrs;
+								let mut added = attrs - cursor_attrs;
+								// Bold and dim are turned off together
+								if removed.intersects(Attributes::BOLD | Attributes::DIM) {
+									added |= attrs & (Attributes::BOLD | Attributes::DIM);
+								}
+								term.unset_attributes(removed)?;
+								if underline_style != UnderlineStyle::Single
+									&& (added.contains(Attributes::UNDERLINE) || restyle_underline)
+								{
+									added -= Attributes::UNDERLINE;
+									term.underline_style(underline_style)?;
+								} else if restyle_underline {
+									added |= Attributes::UNDERLINE;
+								}
+								term.set_attributes(added)?;
+								cursor_attrs = attrs;
+								cursor_underline_style = underline_style;
+							}
+
+							if cursor_underline_color != front_cell.underline_color {
+								cursor_underline_color = front_cell.underline_color;
+								match cursor_underline_color {
+									Some(color) => term.underline_color(color)?,
+									None => term.default_underline_color()?,
+								}
+							}
+
+							// Half a wide glyph on its own is drawn as a blank, including one that would wrap
+							let mut width = front_cell.symbol.width().max(1) as i32;
+							let continued = back.cell(x + 1, y).is_some_and(|cell| cell.symbol.is_continuation());
+							if width == 2 && !continued {
+								width = 1;
+							}
+							match &front_cell.symbol {
+								Symbol::Continuation => term.write_char(' ')?,
+								_ if width < front_cell.symbol.width() as i32 => term.write_char(' ')?,
+								Symbol::Char(c) => term.write_char(*c)?,
+								Symbol::Cluster(s) => term.write_str(s)?,
+							}
+							cursor_x += width;
 						}
-						// FIXME what about double width chars?
-						cursor_x += 1;
 					}
 				}
 			}
 		}
+		if cursor_link.is_some() {
+			term.hyperlink(None)?;
+		}
 		// Move cursor to the top so things don't jump around when resizing the terminal
 		term.move_to(0, 0)?;
 		term.flush()?;
@@ -178,3 +380,127 @@ impl TerminalCanvas {
 		Ok(())
 	}
 }
+
+// Overwriting either half of a wide glyph erases all of it, so both halves are
+// always redrawn together
+fn dirty_cells(front: &Canvas, back: &Canvas, y: i32) -> Vec<bool> {
+	let width = back.width() as i32;
+	let cells: Vec<_> = (0..width).map(|x| (front.cell(x, y), back.cell(x, y))).collect();
+	let mut dirty: Vec<bool> = cells.iter().map(|(front, back)| front != back).collect();
+
+	let is = |x: usize, test: fn(&Symbol) -> bool| {
+		let (front, back) = cells[x];
+		front.is_some_and(|cell| test(&cell.symbol)) || back.is_some_and(|cell| test(&cell.symbol))
+	};
+	for x in (1..dirty.len()).rev() {
+		if dirty[x] && is(x, Symbol::is_continuation) {
+			dirty[x - 1] = true;
+		}
+	}
+	for x in 0..dirty.len().saturating_sub(1) {
+		if dirty[x] && is(x, Symbol::is_wide) {
+			dirty[x + 1] = true;
+		}
+	}
+
+	dirty
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn virtual_canvas(width: u32) -> TerminalCanvas<VirtualTerminal> {
+		TerminalCanvas::with_backend(VirtualTerminal::new(width, 2))
+	}
+
+	fn cell(symbol: Symbol) -> Cell {
+		Cell {
+			fg: Color::rgb(255, 255, 255),
+			symbol,
+			..Cell::null()
+		}
+	}
+
+	// Set cells on the top line, without the fixing up draw_text does
+	fn put(canvas: &mut TerminalCanvas<VirtualTerminal>, x: i32, symbols: &[Symbol]) {
+		for (i, symbol) in symbols.iter().enumerate() {
+			canvas.set_cell(x + i as i32, 0, cell(symbol.clone()));
+		}
+	}
+
+	fn present(canvas: &mut TerminalCanvas<VirtualTerminal>) -> String {
+		canvas.present().unwrap();
+		let vt = canvas.backend();
+		assert_eq!(vt.line(1).trim_end(), "", "nothing wraps onto the next line");
+		vt.line(0).trim_end().to_string()
+	}
+
+	const WIDE: Symbol = Symbol::Char('你');
+	const CONTINUATION: Symbol = Symbol::Continuation;
+
+	#[test]
+	fn wide_glyphs() {
+		let mut canvas = virtual_canvas(4);
+		put(&mut canvas, 0, &['a'.into(), WIDE, CONTINUATION]);
+		assert_eq!(present(&mut canvas), "a你");
+		assert_eq!(canvas.backend().cell(2, 0).unwrap().symbol, CONTINUATION);
+
+		// Without room for its second half it's a blank, rather than wrapping
+		let mut canvas = virtual_canvas(4);
+		put(&mut canvas, 0, &['a'.into(), 'b'.into(), 'c'.into(), WIDE]);
+		assert_eq!(present(&mut canvas), "abc");
+	}
+
+	#[test]
+	fn overwriting_wide_glyphs() {
+		// The left half, leaving a Continuation behind
+		let mut canvas = virtual_canvas(4);
+		put(&mut canvas, 0, &[WIDE, CONTINUATION]);
+		present(&mut canvas);
+		put(&mut canvas, 0, &['x'.into()]);
+		assert_eq!(present(&mut canvas), "x");
+		assert_ne!(canvas.backend().cell(1, 0).unwrap().symbol, CONTINUATION);
+		put(&mut canvas, 1, &['w'.into()]);
+		assert_eq!(present(&mut canvas), "xw");
+
+		// The right half
+		let mut canvas = virtual_canvas(4);
+		put(&mut canvas, 1, &[WIDE, CONTINUATION]);
+		present(&mut canvas);
+		put(&mut canvas, 2, &['y'.into()]);
+		assert_eq!(present(&mut canvas), "  y");
+		// Which erased the left half too, so it has to be drawn again
+		put(&mut canvas, 1, &[WIDE, CONTINUATION]);
+		assert_eq!(present(&mut canvas), " 你");
+
+		// A wide glyph over two narrow ones, then narrow ones over it again
+		let mut canvas = virtual_canvas(4);
+		put(&mut canvas, 0, &['a'.into(), 'b'.into(), 'c'.into()]);
+		present(&mut canvas);
+		put(&mut canvas, 1, &[WIDE, CONTINUATION]);
+		assert_eq!(present(&mut canvas), "a你");
+		put(&mut canvas, 1, &['d'.into(), 'e'.into()]);
+		assert_eq!(present(&mut canvas), "ade");
+	}
+
+	#[test]
+	fn left_over_continuation() {
+		let mut canvas = virtual_canvas(4);
+		put(&mut canvas, 1, &['a'.into(), CONTINUATION, 'b'.into()]);
+		assert_eq!(present(&mut canvas), " a b");
+		assert_ne!(canvas.backend().cell(2, 0).unwrap().symbol, CONTINUATION);
+	}
+
+	#[test]
+	fn combining_clusters() {
+		let mut canvas = virtual_canvas(4);
+		put(&mut canvas, 0, &["e\u{301}".into(), 'x'.into()]);
+		assert_eq!(present(&mut canvas), "e\u{301}x");
+
+		// Redrawing the next cell leaves the cluster alone
+		put(&mut canvas, 1, &['z'.into()]);
+		assert_eq!(present(&mut canvas), "e\u{301}z");
+		assert_eq!(canvas.backend().cell(0, 0).unwrap().symbol, Symbol::from("e\u{301}"));
+	}
+}
@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, OnceLock};
+
+// Every URL is stored once and never freed, so cells only carry a small id and
+// comparing them in the diff stays cheap
+#[derive(Default)]
+struct Links {
+	urls: Vec<Arc<str>>,
+	ids: HashMap<Arc<str>, u32>,
+}
+
+fn links() -> &'static Mutex<Links> {
+	static LINKS: OnceLock<Mutex<Links>> = OnceLock::new();
+	LINKS.get_or_init(Default::default)
+}
+
+// A hyperlink target, drawn with OSC 8 so the terminal makes the text clickable
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Link(u32);
+
+impl Link {
+	pub fn new(url: &str) -> Self {
+		let mut links = links().lock().unwrap();
+		if let Some(&id) = links.ids.get(url) {
+			return Self(id);
+		}
+		let id = links.urls.len() as u32;
+		let url: Arc<str> = url.into();
+		links.urls.push(url.clone());
+		links.ids.insert(url, id);
+		Self(id)
+	}
+
+	pub fn id(&self) -> u32 {
+		self.0
+	}
+
+	pub fn url(&self) -> Arc<str> {
+		links().lock().unwrap().urls[self.0 as usize].clone()
+	}
+}
+
+impl fmt::Debug for Link {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_tuple("Link").field(&self.0).field(&self.url()).finish()
+	}
+}
+
+// OSC 8 sequence to start drawing `link`, or to stop drawing any link. The id
+// lets the terminal treat a link split across lines as one
+pub(crate) fn hyperlink_sequence(link: Option<Link>) -> String {
+	match link {
+		Some(link) => {
+			// Control chars would end the sequence early
+			let url: String = link.url().chars().filter(|c| !c.is_control()).collect();
+			format!("\x1b]8;id={};{}\x1b\\", link.id(), url)
+		}
+		None => "\x1b]8;;\x1b\\".to_string(),
+	}
+}
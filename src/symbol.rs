@@ -0,0 +1,84 @@
+use std::fmt;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+// What's drawn in a single cell. Wide glyphs take up two cells, with a Continuation in the second
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Symbol {
+	Char(char),
+	// A grapheme cluster of several chars, e.g. a letter with combining accents or an emoji sequence
+	Cluster(Box<str>),
+	Continuation,
+}
+
+impl Default for Symbol {
+	fn default() -> Self {
+		Self::Char('\0')
+	}
+}
+
+impl Symbol {
+	// Only the first grapheme cluster of `text` is kept
+	pub fn new(text: &str) -> Self {
+		let grapheme = text.graphemes(true).next().unwrap_or("\0");
+		let mut chars = grapheme.chars();
+		match (chars.next(), chars.next()) {
+			(Some(c), None) => Self::Char(c),
+			_ => Self::Cluster(grapheme.into()),
+		}
+	}
+
+	// How many columns the terminal will use to draw it
+	pub fn width(&self) -> u32 {
+		let width = match self {
+			Self::Char('\0') | Self::Continuation => return 0,
+			Self::Char(c) => c.width().unwrap_or(1),
+			Self::Cluster(s) => s.width(),
+		};
+		width.clamp(1, 2) as u32
+	}
+
+	pub fn is_wide(&self) -> bool {
+		self.width() == 2
+	}
+
+	pub fn is_continuation(&self) -> bool {
+		*self == Self::Continuation
+	}
+
+	pub fn as_char(&self) -> Option<char> {
+		match self {
+			Self::Char(c) => Some(*c),
+			_ => None,
+		}
+	}
+}
+
+impl From<char> for Symbol {
+	fn from(c: char) -> Self {
+		Self::Char(c)
+	}
+}
+
+impl From<&str> for Symbol {
+	fn from(text: &str) -> Self {
+		Self::new(text)
+	}
+}
+
+impl PartialEq<char> for Symbol {
+	fn eq(&self, c: &char) -> bool {
+		*self == Self::Char(*c)
+	}
+}
+
+impl fmt::Display for Symbol {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Char('\0') => f.write_str(" "),
+			Self::Char(c) => write!(f, "{}", c),
+			Self::Cluster(s) => f.write_str(s),
+			Self::Continuation => Ok(()),
+		}
+	}
+}
@@ -1,6 +1,6 @@
 use super::Terminal;
 use crate::Event;
-use std::sync::mpsc;
+use std::sync::{mpsc, Mutex};
 
 impl Terminal {
 	pub fn event_channel(&mut self) -> mpsc::Receiver<Event> {
@@ -11,3 +11,8 @@ impl Terminal {
 		rx
 	}
 }
+
+// Send an event to every listener, forgetting any that have hung up
+pub(crate) fn broadcast(listeners: &Mutex<Vec<mpsc::Sender<Event>>>, event: Event) {
+	listeners.lock().unwrap().retain(|tx| tx.send(event.clone()).is_ok());
+}
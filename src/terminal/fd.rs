@@ -0,0 +1,152 @@
+use libc::{c_int, nfds_t, poll, pollfd, POLLIN};
+use std::io::{self, Read, Write};
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+
+// Unbuffered reader over a raw file descriptor, so polling the fd tells us
+// the truth about whether more input is waiting
+pub(crate) struct FdReader {
+	fd: RawFd,
+}
+
+impl FdReader {
+	pub fn new(fd: RawFd) -> Self {
+		Self { fd }
+	}
+
+	pub fn poll(&self, timeout: Option<Duration>) -> bool {
+		!poll_readable(&[self.fd], timeout).is_empty()
+	}
+}
+
+// Wait for any of the fds to be readable, returning the ones that are
+pub(crate) fn poll_readable(fds: &[RawFd], timeout: Option<Duration>) -> Vec<RawFd> {
+	let mut pollfds: Vec<pollfd> = fds
+		.iter()
+		.map(|&fd| pollfd {
+			fd,
+			events: POLLIN,
+			revents: 0,
+		})
+		.collect();
+	let timeout = match timeout {
+		Some(timeout) => timeout.as_millis().min(c_int::MAX as u128) as c_int,
+		None => -1,
+	};
+	loop {
+		let ready = unsafe { poll(pollfds.as_mut_ptr(), pollfds.len() as nfds_t, timeout) };
+		if ready < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+			continue;
+		}
+		return pollfds
+			.iter()
+			.filter(|pollfd| pollfd.revents != 0)
+			.map(|pollfd| pollfd.fd)
+			.collect();
+	}
+}
+
+// Reads from an fd until the stop pipe is written to, then reports end of file
+pub(crate) struct StoppableReader {
+	reader: FdReader,
+	stop_fd: RawFd,
+}
+
+impl StoppableReader {
+	pub fn new(fd: RawFd, stop_fd: RawFd) -> Self {
+		Self {
+			reader: FdReader::new(fd),
+			stop_fd,
+		}
+	}
+
+	pub fn poll(&self, timeout: Option<Duration>) -> bool {
+		let ready = poll_readable(&[self.reader.fd, self.stop_fd], timeout);
+		!ready.is_empty() && !ready.contains(&self.stop_fd)
+	}
+}
+
+impl Read for StoppableReader {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+		if !self.poll(None) {
+			return Ok(0);
+		}
+		self.reader.read(buf)
+	}
+}
+
+// Wakes a thread blocked in poll so it can exit
+pub(crate) struct StopPipe {
+	read_fd: RawFd,
+	write_fd: RawFd,
+}
+
+impl StopPipe {
+	pub fn new() -> Result<Self, io::Error> {
+		let (read_fd, write_fd) = pipe()?;
+		Ok(Self { read_fd, write_fd })
+	}
+
+	pub fn fd(&self) -> RawFd {
+		self.read_fd
+	}
+
+	pub fn stop(&self) {
+		unsafe {
+			libc::write(self.write_fd, [0u8].as_ptr() as *const _, 1);
+		}
+	}
+}
+
+impl Drop for StopPipe {
+	fn drop(&mut self) {
+		unsafe {
+			libc::close(self.read_fd);
+			libc::close(self.write_fd);
+		}
+	}
+}
+
+pub(crate) fn pipe() -> Result<(RawFd, RawFd), io::Error> {
+	let mut fds = [0; 2];
+	if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+		return Err(io::Error::last_os_error());
+	}
+	Ok((fds[0], fds[1]))
+}
+
+impl Read for FdReader {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+		let len = unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut _, buf.len()) };
+		if len < 0 {
+			Err(io::Error::last_os_error())
+		} else {
+			Ok(len as usize)
+		}
+	}
+}
+
+pub(crate) struct FdWriter {
+	fd: RawFd,
+}
+
+impl FdWriter {
+	pub fn new(fd: RawFd) -> Self {
+		Self { fd }
+	}
+}
+
+impl Write for FdWriter {
+	fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+		let len = unsafe { libc::write(self.fd, buf.as_ptr() as *const _, buf.len()) };
+		if len < 0 {
+			Err(io::Error::last_os_error())
+		} else {
+			Ok(len as usize)
+		}
+	}
+
+	fn flush(&mut self) -> Result<(), io::Error> {
+		Ok(())
+	}
+}
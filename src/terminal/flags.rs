@@ -1,5 +1,5 @@
 use super::Terminal;
-use libc::{tcflag_t, tcgetattr, tcsetattr, termios, STDIN_FILENO, TCSANOW};
+use libc::{tcflag_t, tcgetattr, tcsetattr, termios, TCSANOW};
 
 impl Terminal {
 	pub fn term_ios(&self) -> termios {
@@ -26,7 +26,7 @@ impl Terminal {
 		};
 
 		unsafe {
-			tcgetattr(STDIN_FILENO, &mut ios);
+			tcgetattr(self.in_fd, &mut ios);
 		}
 
 		ios
@@ -34,7 +34,7 @@ impl Terminal {
 
 	pub fn set_term_ios(&mut self, ios: termios) {
 		unsafe {
-			tcsetattr(STDIN_FILENO, TCSANOW, &ios);
+			tcsetattr(self.in_fd, TCSANOW, &ios);
 		}
 	}
 
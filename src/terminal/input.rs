@@ -1,186 +1,574 @@
+use super::events::broadcast;
+use super::fd::{FdReader, StopPipe, StoppableReader};
 use super::Terminal;
-use crate::{Event, MouseButton};
-use libc::{c_int, sighandler_t, signal, SIGINT};
-use std::io::{self, Bytes, Read};
-use std::iter::Peekable;
-use std::thread;
+use crate::{Event, InterruptPolicy, Key, Modifiers, MouseButton};
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 const ESC: char = '\x1b';
-
-extern "C" fn handle_interrupt(_sig: c_int) {
-	// Hacks to cleanup the terminal
-	let mut term = Terminal::new();
-	let _ = term.main_screen();
-	let _ = term.show_cursor();
-	let _ = term.normal_mode();
-	let _ = term.disable_mouse_move();
-	let _ = term.disable_mouse();
-	let _ = term.flush();
-	std::process::exit(0);
-}
+const PASTE_END: &[u8] = b"\x1b[201~";
+pub const DEFAULT_ESCAPE_TIMEOUT: Duration = Duration::from_millis(50);
 
 impl Terminal {
-	pub fn listen_for_events(&mut self) {
-		// Catch ctrl+c and cleanup
-		// FIXME do this in a nicer way
-		unsafe {
-			signal(SIGINT, handle_interrupt as sighandler_t);
-		}
+	pub fn escape_timeout(&self) -> Duration {
+		self.escape_timeout
+	}
+
+	// How long to wait after an ESC byte before deciding it was the Esc key
+	pub fn set_escape_timeout(&mut self, timeout: Duration) {
+		self.escape_timeout = timeout;
+	}
 
+	// Start reading input on a background thread, stopping any previous one first
+	pub fn listen_for_events(&mut self) -> Result<(), io::Error> {
+		self.stop_listening();
+
+		let stop = StopPipe::new()?;
 		let listeners = self.event_channels.clone();
-		self.event_thread = Some(thread::spawn(move || {
-			let mut parser = InputParser::new(io::stdin());
+		let mut parser = InputParser::new(StoppableReader::new(self.in_fd, stop.fd()));
+		parser.set_escape_timeout(self.escape_timeout);
+		parser.set_interrupt_policy(self.interrupt_policy);
+		let handle = thread::spawn(move || {
 			while let Some(event) = parser.next_event() {
-				for tx in &*listeners.lock().unwrap() {
-					tx.send(event.clone()).expect("Failed to send event");
-				}
+				broadcast(&listeners, event);
 			}
-		}));
+		});
+
+		self.event_thread = Some(EventThread { handle, stop });
+		Ok(())
+	}
+
+	// Stop reading input, waiting for the thread to finish so nothing else is consumed
+	pub fn stop_listening(&mut self) {
+		if let Some(thread) = self.event_thread.take() {
+			thread.stop.stop();
+			let _ = thread.handle.join();
+		}
+	}
+}
+
+pub(crate) struct EventThread {
+	handle: JoinHandle<()>,
+	stop: StopPipe,
+}
+
+fn parse_sub_params(params: &str) -> Vec<Vec<u32>> {
+	params
+		.split(';')
+		.map(|param| param.split(':').map(|sub| sub.parse().unwrap_or(0)).collect())
+		.collect()
+}
+
+fn parse_params(params: &str) -> Vec<u32> {
+	// Only the first value of each parameter, ignoring anything after a ':'
+	parse_sub_params(params).iter().map(|param| param[0]).collect()
+}
+
+// xterm and kitty send modifiers as a parameter of 1 + the modifier bits
+fn xterm_modifiers(param: Option<u32>) -> Modifiers {
+	match param {
+		Some(param) if param > 1 => Modifiers::from_bits_truncate((param - 1) as u8),
+		_ => Modifiers::empty(),
+	}
+}
+
+fn add_modifiers(event: Event, extra: Modifiers) -> Event {
+	match event {
+		Event::KeyPress(c, modifiers) => Event::KeyPress(c, modifiers | extra),
+		Event::Key(key, modifiers) => Event::Key(key, modifiers | extra),
+		event => event,
+	}
+}
+
+// Decode the button byte used by the X10, urxvt and SGR mouse protocols
+fn mouse_button(code: u32) -> (MouseButton, Modifiers, bool) {
+	let mut modifiers = Modifiers::empty();
+	if code & 4 != 0 {
+		modifiers |= Modifiers::SHIFT;
+	}
+	if code & 8 != 0 {
+		modifiers |= Modifiers::ALT;
+	}
+	if code & 16 != 0 {
+		modifiers |= Modifiers::CTRL;
+	}
+	let moving = code & 32 != 0;
+
+	let button = if code & 64 != 0 {
+		match code & 3 {
+			0 => MouseButton::WheelUp,
+			1 => MouseButton::WheelDown,
+			_ => MouseButton::Unknown,
+		}
+	} else {
+		match code & 3 {
+			0 => MouseButton::Left,
+			1 => MouseButton::Middle,
+			2 => MouseButton::Right,
+			_ => MouseButton::None,
+		}
+	};
+
+	(button, modifiers, moving)
+}
+
+// X10 and urxvt don't say which button was released, they send button 3 instead
+fn is_legacy_release(code: u32) -> bool {
+	code & (3 | 32 | 64) == 3
+}
+
+fn mouse_event(code: u32, x: u32, y: u32, released: bool) -> Event {
+	let (button, modifiers, mouse_move) = mouse_button(code);
+	if mouse_move {
+		Event::MouseMove(button, x, y, modifiers)
+	} else if released {
+		Event::MouseUp(button, x, y, modifiers)
+	} else {
+		Event::MouseDown(button, x, y, modifiers)
+	}
+}
+
+// Keys sent as `CSI <code> ~`
+fn tilde_key(code: u32) -> Option<Key> {
+	let key = match code {
+		1 | 7 => Key::Home,
+		2 => Key::Insert,
+		3 => Key::Delete,
+		4 | 8 => Key::End,
+		5 => Key::PageUp,
+		6 => Key::PageDown,
+		11..=15 => Key::F((code - 10) as u8),
+		17..=21 => Key::F((code - 11) as u8),
+		23..=26 => Key::F((code - 12) as u8),
+		28 | 29 => Key::F((code - 13) as u8),
+		31..=34 => Key::F((code - 14) as u8),
+		_ => return None,
+	};
+	Some(key)
+}
+
+// Keys sent by the kitty keyboard protocol as `CSI <code>:<shifted> ; <modifiers> ; <text> u`
+fn kitty_key(fields: &[Vec<u32>], modifiers: Modifiers) -> Option<Key> {
+	let code = fields[0][0];
+	let key = match code {
+		8 | 127 => Key::Backspace,
+		9 => Key::Tab,
+		13 => Key::Enter,
+		27 => Key::Esc,
+		57376..=57398 => Key::F((code - 57376 + 13) as u8),
+		// Keypad
+		57399..=57408 => Key::Char((b'0' + (code - 57399) as u8) as char),
+		57409 => Key::Char('.'),
+		57410 => Key::Char('/'),
+		57411 => Key::Char('*'),
+		57412 => Key::Char('-'),
+		57413 => Key::Char('+'),
+		57414 => Key::Enter,
+		57415 => Key::Char('='),
+		57416 => Key::Char(','),
+		57417 => Key::Left,
+		57418 => Key::Right,
+		57419 => Key::Up,
+		57420 => Key::Down,
+		57421 => Key::PageUp,
+		57422 => Key::PageDown,
+		57423 => Key::Home,
+		57424 => Key::End,
+		57425 => Key::Insert,
+		57426 => Key::Delete,
+		// Media and lone modifier keys
+		57344..=63743 => return None,
+		_ => {
+			// Prefer the text the key typed, then its shifted value, then the key itself
+			let text = fields.get(2).map(|text| text[0]).filter(|&c| c > 0);
+			let shifted = fields[0]
+				.get(1)
+				.copied()
+				.filter(|&c| c > 0 && modifiers.contains(Modifiers::SHIFT));
+			Key::Char(char::from_u32(text.or(shifted).unwrap_or(code))?)
+		}
+	};
+	Some(key)
+}
+
+fn key_event(key: Key, modifiers: Modifiers, kind: u32) -> Event {
+	match (kind, key) {
+		(2, key) => Event::KeyRepeat(key, modifiers),
+		(3, key) => Event::KeyRelease(key, modifiers),
+		(_, Key::Char(c)) => Event::KeyPress(c, modifiers),
+		(_, key) => Event::Key(key, modifiers),
 	}
 }
 
-fn is_ctrl_char(c: char) -> bool {
-	match c {
-		'D' | 'E' | 'H' | 'M' | 'N' | 'O' | 'P' | 'V' | 'W' | 'X' | 'Z' | '[' | '\\' | ']' | '^' | '_' => true,
-		_ => false,
+// Keys sent as `CSI <letter>` or `SS3 <letter>`
+fn letter_key(c: char) -> Option<Key> {
+	let key = match c {
+		'A' => Key::Up,
+		'B' => Key::Down,
+		'C' => Key::Right,
+		'D' => Key::Left,
+		'H' => Key::Home,
+		'F' => Key::End,
+		'Z' => Key::BackTab,
+		'P' => Key::F(1),
+		'Q' => Key::F(2),
+		'R' => Key::F(3),
+		'S' => Key::F(4),
+		_ => return None,
+	};
+	Some(key)
+}
+
+pub trait InputSource: Read {
+	// Wait for more input, returning false if none arrives before the timeout
+	fn wait_for_input(&mut self, _timeout: Duration) -> bool {
+		true
+	}
+}
+
+impl InputSource for &[u8] {}
+
+impl InputSource for FdReader {
+	fn wait_for_input(&mut self, timeout: Duration) -> bool {
+		self.poll(Some(timeout))
 	}
 }
 
-pub struct InputParser<R: Read> {
-	stream: Peekable<Bytes<R>>,
+impl InputSource for StoppableReader {
+	fn wait_for_input(&mut self, timeout: Duration) -> bool {
+		self.poll(Some(timeout))
+	}
+}
+
+pub struct InputParser<R: InputSource> {
+	stream: R,
+	buffer: VecDeque<u8>,
+	escape_timeout: Duration,
+	interrupt_policy: InterruptPolicy,
+	// Set while parsing only what has already arrived, see try_next_event
+	non_blocking: bool,
+	incomplete: bool,
+	consumed: Vec<u8>,
+	eof: bool,
+	// Instead of waiting to see if a lone ESC is the Esc key, leave it until its deadline
+	defer_escape: bool,
+	escape_deadline: Option<Instant>,
 }
 
-impl<R: Read> InputParser<R> {
+impl<R: InputSource> InputParser<R> {
 	pub fn new(stream: R) -> Self {
 		Self {
-			stream: stream.bytes().peekable(),
+			stream,
+			buffer: VecDeque::new(),
+			escape_timeout: DEFAULT_ESCAPE_TIMEOUT,
+			interrupt_policy: InterruptPolicy::default(),
+			non_blocking: false,
+			incomplete: false,
+			consumed: vec![],
+			eof: false,
+			defer_escape: false,
+			escape_deadline: None,
 		}
 	}
 
+	pub fn set_escape_timeout(&mut self, timeout: Duration) {
+		self.escape_timeout = timeout;
+	}
+
+	pub fn set_interrupt_policy(&mut self, policy: InterruptPolicy) {
+		self.interrupt_policy = policy;
+	}
+
+	// Stop try_next_event waiting on a lone ESC, see escape_deadline
+	pub fn set_defer_escape(&mut self, defer: bool) {
+		self.defer_escape = defer;
+	}
+
+	// When a deferred lone ESC becomes the Esc key, if nothing else arrives first
+	pub fn escape_deadline(&self) -> Option<Instant> {
+		self.escape_deadline
+	}
+
 	fn read_esc_sequence(&mut self) -> Event {
 		match self.next_char() {
 			'[' => self.read_ctrl_sequence(),
+			'O' => self.read_ss3_sequence(),
 			// TODO other sequence types
 			_ => Event::Unknown,
 		}
 	}
 
 	fn read_ctrl_sequence(&mut self) -> Event {
-		match self.next_char() {
-			'M' => self.read_x10_mouse(),
-			'<' => self.read_sgr_mouse(),
-			c => Event::KeyPress(c),
+		// The Linux console sends F1 to F5 as `CSI [ A` to `CSI [ E`
+		if self.peek_char() == '[' {
+			self.next_char();
+			return match self.next_char() {
+				c @ 'A'..='E' => Event::Key(Key::F(c as u8 - b'A' + 1), Modifiers::empty()),
+				_ => Event::Unknown,
+			};
+		}
+
+		// Read parameter bytes until we hit the final byte
+		let mut params = String::new();
+		let action = loop {
+			let c = self.next_char();
+			if ('0'..='?').contains(&c) {
+				params.push(c);
+			} else {
+				break c;
+			}
+		};
+
+		if params.is_empty() {
+			match action {
+				'M' => return self.read_x10_mouse(),
+				'I' => return Event::FocusGained,
+				'O' => return Event::FocusLost,
+				_ => {}
+			}
+		}
+		if let Some(params) = params.strip_prefix('<') {
+			return self.read_sgr_mouse(params, action);
+		}
+		if params.starts_with(['=', '>', '?']) {
+			// Replies to queries, not input
+			return Event::Unknown;
+		}
+
+		let fields = parse_sub_params(&params);
+		let params = parse_params(&params);
+		if action == 'M' && params.len() == 3 {
+			return self.read_urxvt_mouse(&params);
+		}
+
+		if action == '~' && params[0] == 200 {
+			return self.read_paste();
+		}
+
+		// Kitty adds the event type after the modifiers, e.g. `CSI 1;5:3A`
+		let modifiers = xterm_modifiers(params.get(1).copied());
+		let kind = fields.get(1).and_then(|field| field.get(1)).copied().unwrap_or(1);
+		let key = match action {
+			'u' => kitty_key(&fields, modifiers),
+			'~' => tilde_key(params[0]),
+			c => letter_key(c),
+		};
+
+		match key {
+			Some(key) => key_event(key, modifiers, kind),
+			None => Event::Unknown,
 		}
 	}
 
-	fn read_x10_mouse(&mut self) -> Event {
-		// TODO
-		Event::Unknown
-	}
-
-	fn read_sgr_mouse(&mut self) -> Event {
-		let mut mouse_move = false;
-		let mut mouse_up = false;
-		// Read mouse button
-		let button = match self.next_char() {
-			'0' => MouseButton::Left,
-			'1' => MouseButton::Middle,
-			'2' => MouseButton::Right,
-			// Moving
-			'3' => {
-				mouse_move = true;
-				match self.next_char() {
-					'2' => MouseButton::Left,
-					'3' => MouseButton::Middle,
-					'4' => MouseButton::Right,
-					'5' => MouseButton::None,
-					_ => MouseButton::Unknown,
-				}
+	fn read_paste(&mut self) -> Event {
+		// Everything up to the end marker is pasted text, even escape sequences
+		let mut bytes = vec![];
+		while !bytes.ends_with(PASTE_END) {
+			match self.read_byte() {
+				Some(byte) => bytes.push(byte),
+				None => break,
 			}
-			// Scrolling
-			'6' => match self.next_char() {
-				'4' => MouseButton::WheelUp,
-				'5' => MouseButton::WheelDown,
-				_ => MouseButton::Unknown,
+		}
+		if bytes.ends_with(PASTE_END) {
+			bytes.truncate(bytes.len() - PASTE_END.len());
+		}
+
+		Event::Paste(String::from_utf8_lossy(&bytes).into_owned())
+	}
+
+	fn read_ss3_sequence(&mut self) -> Event {
+		match self.next_char() {
+			'M' => Event::Key(Key::Enter, Modifiers::empty()),
+			c => match letter_key(c) {
+				Some(key) => Event::Key(key, Modifiers::empty()),
+				None => Event::Unknown,
 			},
-			_ => MouseButton::Unknown,
-		};
+		}
+	}
 
-		// Next byte should be a ';'
-		if self.next_char() != ';' {
+	fn read_x10_mouse(&mut self) -> Event {
+		// Button and coords are each a single byte offset by 32
+		let code = self.next_byte().saturating_sub(32) as u32;
+		let x = self.next_byte().saturating_sub(32).max(1) as u32 - 1;
+		let y = self.next_byte().saturating_sub(32).max(1) as u32 - 1;
+
+		mouse_event(code, x, y, is_legacy_release(code))
+	}
+
+	fn read_urxvt_mouse(&mut self, params: &[u32]) -> Event {
+		// Same as X10, but the values are sent as decimal numbers
+		let code = params[0].saturating_sub(32);
+		let x = params[1].max(1) - 1;
+		let y = params[2].max(1) - 1;
+
+		mouse_event(code, x, y, is_legacy_release(code))
+	}
+
+	fn read_sgr_mouse(&mut self, params: &str, action: char) -> Event {
+		let params = parse_params(params);
+		if params.len() != 3 {
 			// Invalid mouse coords
 			return Event::Unknown;
 		}
 
-		let mut x = 0;
-		let mut y = 0;
-		let mut token = 0;
-		let mut ascii_num = String::new();
-		self.scan_chars(|c| {
-			if c == ';' || c == 'm' || c == 'M' {
-				if token == 0 {
-					x = ascii_num.parse::<u32>().unwrap_or(1) - 1;
-					ascii_num = String::new();
-				} else if token == 1 {
-					y = ascii_num.parse::<u32>().unwrap_or(1) - 1;
-					ascii_num = String::new();
-				} else {
-					// 3D mouse?!
-					// Too many mouse coords
-					return false;
-				}
-				token += 1;
+		let x = params[1].max(1) - 1;
+		let y = params[2].max(1) - 1;
 
-				if c == 'm' {
-					mouse_up = true;
-				}
+		mouse_event(params[0], x, y, action == 'm')
+	}
 
-				// scan until we hit an 'm' or 'M'
-				return c != 'm' && c != 'M';
+	pub fn next_event(&mut self) -> Option<Event> {
+		// Nothing left to read
+		let c = self.read_byte()? as char;
+		match self.read_key(c) {
+			Event::KeyPress('c', Modifiers::CTRL) if self.interrupt_policy == InterruptPolicy::Event => {
+				Some(Event::Interrupt)
 			}
+			event => Some(event),
+		}
+	}
 
-			// Read coord digit
-			if c >= '0' && c <= '9' {
-				ascii_num.push(c);
-				true
-			} else {
-				// Invalid mouse position
-				false
+	pub fn is_eof(&self) -> bool {
+		self.eof
+	}
+
+	// Parse an event from input that has already arrived, without blocking. A sequence
+	// that's only partly arrived is left in the buffer until the rest turns up
+	pub fn try_next_event(&mut self) -> Option<Event> {
+		self.non_blocking = true;
+		self.incomplete = false;
+		self.consumed.clear();
+		let event = self.next_event();
+		self.non_blocking = false;
+
+		if self.incomplete {
+			for &byte in self.consumed.iter().rev() {
+				self.buffer.push_front(byte);
 			}
-		});
+			return None;
+		}
+		event
+	}
 
-		if mouse_move {
-			Event::MouseMove(button, x, y)
-		} else if mouse_up {
-			Event::MouseUp(button, x, y)
-		} else {
-			Event::MouseDown(button, x, y)
+	fn read_key(&mut self, c: char) -> Event {
+		let none = Modifiers::empty();
+		match c {
+			// Sequences arrive all at once, so if nothing follows quickly it was the Esc key
+			ESC if self.is_lone_escape() => Event::Key(Key::Esc, none),
+			// Deferred, try_next_event puts it back until more input or the deadline arrives
+			ESC if self.incomplete => Event::Unknown,
+			ESC => match self.peek_char() {
+				// Parsing escape sequence
+				'[' | 'O' => self.read_esc_sequence(),
+				ESC => Event::Key(Key::Esc, none),
+				// Alt sends the key prefixed with an escape
+				_ => {
+					let c = self.next_char();
+					add_modifiers(self.read_key(c), Modifiers::ALT)
+				}
+			},
+			'\r' | '\n' => Event::Key(Key::Enter, none),
+			'\t' => Event::Key(Key::Tab, none),
+			'\x7f' | '\x08' => Event::Key(Key::Backspace, none),
+			// Ctrl+A to Ctrl+Z
+			'\x01'..='\x1a' => Event::KeyPress((c as u8 + b'a' - 1) as char, Modifiers::CTRL),
+			// Ctrl+\ to Ctrl+_
+			'\x1c'..='\x1f' => Event::KeyPress((c as u8 + b'@') as char, Modifiers::CTRL),
+			// Start of a multi-byte UTF-8 character
+			'\u{80}'..='\u{ff}' => Event::KeyPress(self.read_utf8_char(c as u8), none),
+			// Normal key press
+			_ => Event::KeyPress(c, none),
 		}
 	}
 
-	fn scan_chars<F: FnMut(char) -> bool>(&mut self, mut scanner: F) {
-		while scanner(self.next_char()) {}
+	// Invalid UTF-8 is reported as a key press of U+FFFD
+	fn read_utf8_char(&mut self, first: u8) -> char {
+		let len = match first {
+			0xc2..=0xdf => 2,
+			0xe0..=0xef => 3,
+			0xf0..=0xf4 => 4,
+			_ => return char::REPLACEMENT_CHARACTER,
+		};
+
+		let mut bytes = [first, 0, 0, 0];
+		for byte in bytes.iter_mut().take(len).skip(1) {
+			// Leave anything that isn't a continuation byte to be parsed as the next event
+			if self.peek_byte() & 0xc0 != 0x80 {
+				return char::REPLACEMENT_CHARACTER;
+			}
+			*byte = self.next_byte();
+		}
+
+		match std::str::from_utf8(&bytes[..len]) {
+			Ok(s) => s.chars().next().unwrap_or(char::REPLACEMENT_CHARACTER),
+			Err(_) => char::REPLACEMENT_CHARACTER,
+		}
 	}
 
-	pub fn next_event(&mut self) -> Option<Event> {
-		let c = self.next_char();
+	fn fill_buffer(&mut self) -> bool {
+		if self.non_blocking && !self.stream.wait_for_input(Duration::ZERO) {
+			self.incomplete = true;
+			return false;
+		}
+		let mut bytes = [0; 1024];
+		loop {
+			match self.stream.read(&mut bytes) {
+				Ok(0) => {
+					self.eof = true;
+					return false;
+				}
+				Ok(len) => {
+					self.buffer.extend(&bytes[..len]);
+					return true;
+				}
+				Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+				Err(_) => {
+					self.eof = true;
+					return false;
+				}
+			}
+		}
+	}
 
-		if c == ESC && is_ctrl_char(self.peek_char()) {
-			// Parsing escape sequence
-			Some(self.read_esc_sequence())
+	fn is_lone_escape(&mut self) -> bool {
+		if !self.defer_escape || !self.non_blocking {
+			return !self.has_input(self.escape_timeout);
+		}
+		let deadline = *self
+			.escape_deadline
+			.get_or_insert_with(|| Instant::now() + self.escape_timeout);
+		if self.has_input(Duration::ZERO) {
+			self.escape_deadline = None;
+			false
+		} else if self.eof || Instant::now() >= deadline {
+			self.escape_deadline = None;
+			true
 		} else {
-			// Normal key press
-			Some(Event::KeyPress(c))
+			self.incomplete = true;
+			false
 		}
 	}
 
-	pub fn next_byte(&mut self) -> u8 {
-		if let Some(Ok(byte)) = self.stream.next() {
-			byte
-		} else {
-			// Failed to read byte
-			0
+	fn has_input(&mut self, timeout: Duration) -> bool {
+		!self.buffer.is_empty() || (self.stream.wait_for_input(timeout) && self.fill_buffer())
+	}
+
+	fn read_byte(&mut self) -> Option<u8> {
+		if self.buffer.is_empty() && !self.fill_buffer() {
+			return None;
+		}
+		let byte = self.buffer.pop_front()?;
+		if self.non_blocking {
+			self.consumed.push(byte);
 		}
+		Some(byte)
+	}
+
+	pub fn next_byte(&mut self) -> u8 {
+		// Failed to read byte
+		self.read_byte().unwrap_or(0)
 	}
 
 	pub fn next_char(&mut self) -> char {
@@ -188,15 +576,240 @@ impl<R: Read> InputParser<R> {
 	}
 
 	pub fn peek_byte(&mut self) -> u8 {
-		if let Some(Ok(byte)) = self.stream.peek() {
-			*byte
-		} else {
+		if self.buffer.is_empty() && !self.fill_buffer() {
 			// Failed to peek byte
-			0
+			return 0;
 		}
+		self.buffer.front().copied().unwrap_or(0)
 	}
 
 	pub fn peek_char(&mut self) -> char {
 		self.peek_byte() as _
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::cell::RefCell;
+	use std::iter;
+	use std::rc::Rc;
+
+	const NONE: Modifiers = Modifiers::empty();
+
+	fn parse(input: &[u8]) -> Vec<Event> {
+		let mut parser = InputParser::new(input);
+		iter::from_fn(|| parser.next_event()).collect()
+	}
+
+	fn assert_parses(cases: &[(&[u8], Event)]) {
+		for (input, event) in cases {
+			assert_eq!(
+				parse(input),
+				vec![event.clone()],
+				"parsing {:?}",
+				String::from_utf8_lossy(input)
+			);
+		}
+	}
+
+	#[test]
+	fn keys() {
+		assert_parses(&[
+			(b"\x1b[A", Event::Key(Key::Up, NONE)),
+			(b"\x1b[B", Event::Key(Key::Down, NONE)),
+			(b"\x1b[C", Event::Key(Key::Right, NONE)),
+			(b"\x1b[D", Event::Key(Key::Left, NONE)),
+			(b"\x1bOA", Event::Key(Key::Up, NONE)),
+			(b"\x1b[H", Event::Key(Key::Home, NONE)),
+			(b"\x1b[F", Event::Key(Key::End, NONE)),
+			(b"\x1b[1~", Event::Key(Key::Home, NONE)),
+			(b"\x1b[2~", Event::Key(Key::Insert, NONE)),
+			(b"\x1b[3~", Event::Key(Key::Delete, NONE)),
+			(b"\x1b[4~", Event::Key(Key::End, NONE)),
+			(b"\x1b[5~", Event::Key(Key::PageUp, NONE)),
+			(b"\x1b[6~", Event::Key(Key::PageDown, NONE)),
+			(b"\x1b[Z", Event::Key(Key::BackTab, NONE)),
+			(b"\x1bOM", Event::Key(Key::Enter, NONE)),
+			(b"\x1bOP", Event::Key(Key::F(1), NONE)),
+			(b"\x1bOS", Event::Key(Key::F(4), NONE)),
+			(b"\x1b[15~", Event::Key(Key::F(5), NONE)),
+			(b"\x1b[24~", Event::Key(Key::F(12), NONE)),
+			(b"\x1b[[A", Event::Key(Key::F(1), NONE)),
+			(b"\x1b[[E", Event::Key(Key::F(5), NONE)),
+			(b"\r", Event::Key(Key::Enter, NONE)),
+			(b"\t", Event::Key(Key::Tab, NONE)),
+			(b"\x7f", Event::Key(Key::Backspace, NONE)),
+			(b"a", Event::KeyPress('a', NONE)),
+		]);
+	}
+
+	#[test]
+	fn modifiers() {
+		assert_parses(&[
+			(b"\x1b[1;2D", Event::Key(Key::Left, Modifiers::SHIFT)),
+			(b"\x1b[1;5A", Event::Key(Key::Up, Modifiers::CTRL)),
+			(b"\x1b[1;9A", Event::Key(Key::Up, Modifiers::SUPER)),
+			(b"\x1b[3;3~", Event::Key(Key::Delete, Modifiers::ALT)),
+			(b"\x1b[1;6P", Event::Key(Key::F(1), Modifiers::SHIFT | Modifiers::CTRL)),
+			(b"\x1ba", Event::KeyPress('a', Modifiers::ALT)),
+			(b"\x1b\x01", Event::KeyPress('a', Modifiers::ALT | Modifiers::CTRL)),
+			(b"\x01", Event::KeyPress('a', Modifiers::CTRL)),
+			(b"\x1c", Event::KeyPress('\\', Modifiers::CTRL)),
+			(
+				b"\x1b[<16;1;1M",
+				Event::MouseDown(MouseButton::Left, 0, 0, Modifiers::CTRL),
+			),
+		]);
+	}
+
+	#[test]
+	fn utf8() {
+		assert_eq!(
+			parse("é€😀".as_bytes()),
+			vec![
+				Event::KeyPress('é', NONE),
+				Event::KeyPress('€', NONE),
+				Event::KeyPress('😀', NONE),
+			]
+		);
+		assert_eq!(parse(b"\xff"), vec![Event::KeyPress(char::REPLACEMENT_CHARACTER, NONE)]);
+		// A truncated character doesn't swallow the next key
+		assert_eq!(
+			parse(b"\xc3a"),
+			vec![
+				Event::KeyPress(char::REPLACEMENT_CHARACTER, NONE),
+				Event::KeyPress('a', NONE)
+			]
+		);
+	}
+
+	#[test]
+	fn mouse() {
+		assert_parses(&[
+			(b"\x1b[M\x20\x21\x21", Event::MouseDown(MouseButton::Left, 0, 0, NONE)),
+			(b"\x1b[M\x23\x2a\x25", Event::MouseUp(MouseButton::None, 9, 4, NONE)),
+			(
+				b"\x1b[M\x60\x21\x21",
+				Event::MouseDown(MouseButton::WheelUp, 0, 0, NONE),
+			),
+			(b"\x1b[32;10;5M", Event::MouseDown(MouseButton::Left, 9, 4, NONE)),
+			(b"\x1b[35;10;5M", Event::MouseUp(MouseButton::None, 9, 4, NONE)),
+			(b"\x1b[<0;10;5M", Event::MouseDown(MouseButton::Left, 9, 4, NONE)),
+			(b"\x1b[<0;10;5m", Event::MouseUp(MouseButton::Left, 9, 4, NONE)),
+			(b"\x1b[<2;1;1M", Event::MouseDown(MouseButton::Right, 0, 0, NONE)),
+			(b"\x1b[<32;3;4M", Event::MouseMove(MouseButton::Left, 2, 3, NONE)),
+			(b"\x1b[<35;3;4M", Event::MouseMove(MouseButton::None, 2, 3, NONE)),
+			(b"\x1b[<65;1;1M", Event::MouseDown(MouseButton::WheelDown, 0, 0, NONE)),
+		]);
+	}
+
+	#[test]
+	fn paste() {
+		assert_eq!(
+			parse(b"\x1b[200~hi\x1b[A\r\nthere\x1b[201~x"),
+			vec![Event::Paste("hi\x1b[A\r\nthere".into()), Event::KeyPress('x', NONE)]
+		);
+	}
+
+	#[test]
+	fn focus() {
+		assert_parses(&[(b"\x1b[I", Event::FocusGained), (b"\x1b[O", Event::FocusLost)]);
+	}
+
+	#[test]
+	fn escape() {
+		assert_parses(&[(b"\x1b", Event::Key(Key::Esc, NONE))]);
+		assert_eq!(
+			parse(b"\x1b\x1b[A"),
+			vec![Event::Key(Key::Esc, NONE), Event::Key(Key::Up, NONE)]
+		);
+	}
+
+	#[test]
+	fn kitty_keyboard() {
+		assert_parses(&[
+			(b"\x1b[97u", Event::KeyPress('a', NONE)),
+			(b"\x1b[97;5u", Event::KeyPress('a', Modifiers::CTRL)),
+			(b"\x1b[97;1:2u", Event::KeyRepeat(Key::Char('a'), NONE)),
+			(b"\x1b[97;1:3u", Event::KeyRelease(Key::Char('a'), NONE)),
+			(b"\x1b[97:65;2u", Event::KeyPress('A', Modifiers::SHIFT)),
+			(b"\x1b[97;2;65u", Event::KeyPress('A', Modifiers::SHIFT)),
+			(b"\x1b[27u", Event::Key(Key::Esc, NONE)),
+			(b"\x1b[13;3u", Event::Key(Key::Enter, Modifiers::ALT)),
+			(b"\x1b[57376u", Event::Key(Key::F(13), NONE)),
+			(b"\x1b[57399u", Event::KeyPress('0', NONE)),
+			(b"\x1b[57441u", Event::Unknown),
+			(b"\x1b[1;5:3A", Event::KeyRelease(Key::Up, Modifiers::CTRL)),
+			(b"\x1b[?1u", Event::Unknown),
+		]);
+	}
+
+	#[test]
+	fn interrupt() {
+		assert_parses(&[(b"\x03", Event::Interrupt), (b"\x1b[99;5u", Event::Interrupt)]);
+
+		let mut parser = InputParser::new(&b"\x03"[..]);
+		parser.set_interrupt_policy(InterruptPolicy::KeyPress);
+		assert_eq!(parser.next_event(), Some(Event::KeyPress('c', Modifiers::CTRL)));
+	}
+
+	// Input the test hands over a chunk at a time, with nothing waiting in between
+	#[derive(Clone, Default)]
+	struct Arrivals(Rc<RefCell<VecDeque<&'static [u8]>>>);
+
+	impl Arrivals {
+		fn send(&self, chunk: &'static [u8]) {
+			self.0.borrow_mut().push_back(chunk);
+		}
+	}
+
+	impl Read for Arrivals {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			let chunk = self.0.borrow_mut().pop_front().unwrap_or_default();
+			buf[..chunk.len()].copy_from_slice(chunk);
+			Ok(chunk.len())
+		}
+	}
+
+	impl InputSource for Arrivals {
+		fn wait_for_input(&mut self, _timeout: Duration) -> bool {
+			!self.0.borrow().is_empty()
+		}
+	}
+
+	#[test]
+	fn partial_sequences() {
+		let input = Arrivals::default();
+		let mut parser = InputParser::new(input.clone());
+		input.send(b"\x1b[1;");
+		assert_eq!(parser.try_next_event(), None);
+		input.send(b"5A");
+		assert_eq!(parser.try_next_event(), Some(Event::Key(Key::Up, Modifiers::CTRL)));
+		assert_eq!(parser.try_next_event(), None);
+	}
+
+	#[test]
+	fn deferred_escape() {
+		let input = Arrivals::default();
+		let mut parser = InputParser::new(input.clone());
+		parser.set_escape_timeout(Duration::from_millis(20));
+		parser.set_defer_escape(true);
+		input.send(b"\x1b");
+		let start = Instant::now();
+		assert_eq!(parser.try_next_event(), None);
+		assert!(start.elapsed() < Duration::from_millis(20));
+		let deadline = parser.escape_deadline().unwrap();
+
+		thread::sleep(deadline - Instant::now());
+		assert_eq!(parser.try_next_event(), Some(Event::Key(Key::Esc, NONE)));
+		assert_eq!(parser.escape_deadline(), None);
+
+		// The rest of a sequence arriving in time cancels the deadline
+		input.send(b"\x1b");
+		assert_eq!(parser.try_next_event(), None);
+		input.send(b"[A");
+		assert_eq!(parser.try_next_event(), Some(Event::Key(Key::Up, NONE)));
+		assert_eq!(parser.escape_deadline(), None);
+	}
+}
@@ -1,31 +1,92 @@
-use crate::{Color, Event};
-use std::io::{self, Write};
-use std::sync::{mpsc, Arc, Mutex};
-use std::thread::JoinHandle;
+use crate::{
+	hyperlink_sequence, Attributes, Color, ColorDepth, Event, InterruptPolicy, Link, MouseProtocol, UnderlineStyle,
+};
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
 
 mod events;
+mod fd;
 mod flags;
 mod input;
 mod modes;
+mod polling;
 mod resize;
+mod session;
+mod signals;
+#[cfg(feature = "async")]
+mod stream;
+mod suspend;
+
+pub use session::TerminalGuard;
+#[cfg(feature = "async")]
+pub use stream::EventStream;
 
 pub type WriteResult = Result<usize, io::Error>;
 
 pub struct Terminal {
 	event_channels: Arc<Mutex<Vec<mpsc::Sender<Event>>>>,
-	event_thread: Option<JoinHandle<()>>,
-	stdout: io::Stdout,
-	stdin: io::Stdin,
+	mouse_protocol: MouseProtocol,
+	interrupt_policy: InterruptPolicy,
+	escape_timeout: Duration,
+	color_depth: ColorDepth,
+	styled_underlines: bool,
+	session: Arc<Mutex<session::Session>>,
+	in_fd: RawFd,
+	size: Arc<resize::WindowSize>,
+	resize_subscription: Option<signals::Subscription>,
+	event_thread: Option<input::EventThread>,
+	// Registered with the process wide Ctrl+Z handler
+	suspend_id: Option<u64>,
+	poller: Option<polling::Poller>,
+	out_fd: RawFd,
+	// Shared with the session, which flushes it before restoring the terminal
+	output: Arc<Mutex<BufWriter<fd::FdWriter>>>,
+}
+
+impl Default for Terminal {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Drop for Terminal {
+	fn drop(&mut self) {
+		self.stop_listening();
+		self.stop_listening_for_suspend();
+		self.stop_listening_for_resize();
+		// Guards can outlive us, so don't leave our output for them to write
+		let _ = self.flush();
+	}
 }
 
 impl Terminal {
 	pub fn new() -> Self {
+		Self::from_fd(libc::STDIN_FILENO, libc::STDOUT_FILENO)
+	}
+
+	// Use any terminal or pty, the fds must stay open for as long as the Terminal
+	pub fn from_fd(in_fd: RawFd, out_fd: RawFd) -> Self {
 		let listeners = Arc::new(Mutex::new(vec![]));
+		let output = Arc::new(Mutex::new(BufWriter::new(fd::FdWriter::new(out_fd))));
 		let term = Self {
 			event_channels: listeners.clone(),
+			mouse_protocol: MouseProtocol::default(),
+			interrupt_policy: InterruptPolicy::default(),
+			escape_timeout: input::DEFAULT_ESCAPE_TIMEOUT,
+			color_depth: ColorDepth::from_env(),
+			styled_underlines: UnderlineStyle::supported_from_env(),
+			session: Arc::new(Mutex::new(session::Session::new(in_fd, out_fd, output.clone()))),
+			in_fd,
+			size: Arc::new(resize::WindowSize::new()),
+			resize_subscription: None,
 			event_thread: None,
-			stdout: io::stdout(),
-			stdin: io::stdin(),
+			suspend_id: None,
+			poller: None,
+			out_fd,
+			output,
 		};
 
 		term.refresh_size();
@@ -33,20 +94,21 @@ impl Terminal {
 		term
 	}
 
-	pub fn stdin(&self) -> &io::Stdin {
-		&self.stdin
+	// Talk to the controlling terminal directly, so stdin and stdout are free to be piped
+	pub fn open_tty() -> Result<Self, io::Error> {
+		let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+		let fd = tty.as_raw_fd();
+		let term = Self::from_fd(fd, fd);
+		term.session.lock().unwrap().set_tty(tty);
+		Ok(term)
 	}
 
-	pub fn stdout(&self) -> &io::Stdout {
-		&self.stdout
+	pub fn in_fd(&self) -> RawFd {
+		self.in_fd
 	}
 
-	pub fn stdin_mut(&mut self) -> &mut io::Stdin {
-		&mut self.stdin
-	}
-
-	pub fn stdout_mut(&mut self) -> &mut io::Stdout {
-		&mut self.stdout
+	pub fn out_fd(&self) -> RawFd {
+		self.out_fd
 	}
 
 	pub fn size(&self) -> (u32, u32) {
@@ -54,17 +116,21 @@ impl Terminal {
 	}
 
 	pub fn flush(&mut self) -> Result<(), io::Error> {
-		self.stdout.flush()
+		self.output().flush()
 	}
 
 	pub fn write(&mut self, output: &str) -> WriteResult {
-		self.stdout.write(output.as_bytes())
+		self.output().write_all(output.as_bytes())?;
+		Ok(output.len())
+	}
+
+	fn output(&self) -> MutexGuard<'_, BufWriter<fd::FdWriter>> {
+		self.output.lock().unwrap_or_else(PoisonError::into_inner)
 	}
 
 	pub fn write_char(&mut self, output: char) -> WriteResult {
 		let mut bytes = [0; 4];
-		output.encode_utf8(&mut bytes);
-		self.stdout.write(&bytes)
+		self.write(output.encode_utf8(&mut bytes))
 	}
 
 	pub fn csi(&mut self, output: &str) -> WriteResult {
@@ -77,10 +143,12 @@ impl Terminal {
 	}
 
 	pub fn alt_screen(&mut self) -> WriteResult {
+		self.session.lock().unwrap().set_alt_screen(true);
 		self.csi("?1049h")
 	}
 
 	pub fn main_screen(&mut self) -> WriteResult {
+		self.session.lock().unwrap().set_alt_screen(false);
 		self.csi("?1049l")
 	}
 
@@ -88,6 +156,11 @@ impl Terminal {
 		self.csi(&format!("{};{}H", y + 1, x + 1))
 	}
 
+	// Text written after this links to `link`, until it's called again with None
+	pub fn hyperlink(&mut self, link: Option<Link>) -> WriteResult {
+		self.write(&hyperlink_sequence(link))
+	}
+
 	pub fn show_cursor(&mut self) -> WriteResult {
 		self.csi("?25h")
 	}
@@ -96,14 +169,54 @@ impl Terminal {
 		self.csi("?25l")
 	}
 
+	pub fn color_depth(&self) -> ColorDepth {
+		self.color_depth
+	}
+
+	// Overrides what was detected from COLORTERM and TERM
+	pub fn set_color_depth(&mut self, depth: ColorDepth) {
+		self.color_depth = depth;
+	}
+
+	pub fn styled_underlines(&self) -> bool {
+		self.styled_underlines
+	}
+
+	// Overrides what was detected from TERM, TERM_PROGRAM and VTE_VERSION
+	pub fn set_styled_underlines(&mut self, supported: bool) {
+		self.styled_underlines = supported;
+	}
+
+	// Colors are reduced to what the terminal can show, and left out entirely in mono
 	pub fn fg_color(&mut self, color: Color) -> WriteResult {
-		let (r, g, b) = color.as_rgb();
-		self.csi(&format!("38;2;{};{};{}m", r, g, b))
+		self.sgr_color(38, color)
 	}
 
 	pub fn bg_color(&mut self, color: Color) -> WriteResult {
-		let (r, g, b) = color.as_rgb();
-		self.csi(&format!("48;2;{};{};{}m", r, g, b))
+		self.sgr_color(48, color)
+	}
+
+	// Left out without styled underlines, terminals support both or neither
+	pub fn underline_color(&mut self, color: Color) -> WriteResult {
+		if !self.styled_underlines {
+			return Ok(0);
+		}
+		self.sgr_color(58, color)
+	}
+
+	fn sgr_color(&mut self, code: u8, color: Color) -> WriteResult {
+		match self.color_depth.sgr_color(code, color) {
+			Some(params) => self.csi(&format!("{}m", params)),
+			None => Ok(0),
+		}
+	}
+
+	// Go back to underlining in the fg color
+	pub fn default_underline_color(&mut self) -> WriteResult {
+		if !self.styled_underlines {
+			return Ok(0);
+		}
+		self.csi("59m")
 	}
 
 	pub fn fg_8bit_color(&mut self, color: Color) -> WriteResult {
@@ -123,4 +236,27 @@ impl Terminal {
 	pub fn reset(&mut self) -> WriteResult {
 		self.csi("m")
 	}
+
+	pub fn set_attributes(&mut self, attrs: Attributes) -> WriteResult {
+		if attrs.is_empty() {
+			// An empty SGR would reset everything
+			return Ok(0);
+		}
+		self.csi(&format!("{}m", attrs.sgr_on()))
+	}
+
+	// Also turns the underline on, as a single one without styled underlines
+	pub fn underline_style(&mut self, style: UnderlineStyle) -> WriteResult {
+		if !self.styled_underlines {
+			return self.csi("4m");
+		}
+		self.csi(&format!("4:{}m", style.sgr_code()))
+	}
+
+	pub fn unset_attributes(&mut self, attrs: Attributes) -> WriteResult {
+		if attrs.is_empty() {
+			return Ok(0);
+		}
+		self.csi(&format!("{}m", attrs.sgr_off()))
+	}
 }
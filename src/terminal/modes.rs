@@ -1,25 +1,58 @@
+use super::session::pop_kitty_keyboard_sequence;
 use super::Terminal;
+use crate::{InterruptPolicy, KittyKeyboardFlags, MouseProtocol};
+use libc::{
+	_POSIX_VDISABLE, BRKINT, CS8, CSIZE, ECHO, ECHONL, ICANON, ICRNL, IEXTEN, IGNBRK, IGNCR, INLCR, ISIG, ISTRIP, IXON,
+	OPOST, PARENB, PARMRK, VINTR, VMIN, VTIME,
+};
 use std::io;
 
 type WriteResult = Result<(), io::Error>;
 
 impl Terminal {
 	pub fn normal_mode(&mut self) {
+		let ios = self.session.lock().unwrap().take_ios();
+		if let Some(ios) = ios {
+			// Put back exactly what we had before entering raw mode
+			self.set_term_ios(ios);
+			return;
+		}
+
 		self.enable_echo();
 		self.enable_canonical();
 		self.enable_flow_control();
 		self.enable_input_processing();
 		self.enable_cr_to_nl();
 		self.enable_ctrl_c();
+		self.enable_interrupt_key();
 	}
 
 	pub fn raw_mode(&mut self) {
+		let ios = self.term_ios();
+		self.session.lock().unwrap().save_ios(ios);
 		self.disable_echo();
 		self.disable_canonical();
 		self.disable_flow_control();
 		self.disable_input_processing();
 		self.disable_cr_to_nl();
-		//self.disable_ctrl_c();
+		if self.interrupt_policy != InterruptPolicy::Signal {
+			// Ctrl+c arrives as input instead of raising SIGINT
+			self.disable_interrupt_key();
+		}
+	}
+
+	// Equivalent of cfmakeraw(3), which also stops ctrl+c and ctrl+z sending signals
+	pub fn full_raw_mode(&mut self) {
+		let mut ios = self.term_ios();
+		self.session.lock().unwrap().save_ios(ios);
+		ios.c_iflag &= !(IGNBRK | BRKINT | PARMRK | ISTRIP | INLCR | IGNCR | ICRNL | IXON);
+		ios.c_oflag &= !OPOST;
+		ios.c_lflag &= !(ECHO | ECHONL | ICANON | ISIG | IEXTEN);
+		ios.c_cflag &= !(CSIZE | PARENB);
+		ios.c_cflag |= CS8;
+		ios.c_cc[VMIN] = 1;
+		ios.c_cc[VTIME] = 0;
+		self.set_term_ios(ios);
 	}
 
 	pub fn enable_echo(&mut self) {
@@ -39,11 +72,11 @@ impl Terminal {
 	}
 
 	pub fn enable_flow_control(&mut self) {
-		self.set_lflag(libc::IXON);
+		self.set_iflag(libc::IXON);
 	}
 
 	pub fn disable_flow_control(&mut self) {
-		self.unset_lflag(libc::IXON);
+		self.unset_iflag(libc::IXON);
 	}
 
 	pub fn enable_input_processing(&mut self) {
@@ -55,11 +88,11 @@ impl Terminal {
 	}
 
 	pub fn enable_cr_to_nl(&mut self) {
-		self.set_lflag(libc::ICRNL);
+		self.set_iflag(libc::ICRNL);
 	}
 
 	pub fn disable_cr_to_nl(&mut self) {
-		self.unset_lflag(libc::ICRNL);
+		self.unset_iflag(libc::ICRNL);
 	}
 
 	pub fn enable_ctrl_c(&mut self) {
@@ -70,22 +103,109 @@ impl Terminal {
 		self.unset_lflag(libc::ISIG);
 	}
 
+	pub fn enable_interrupt_key(&mut self) {
+		let mut ios = self.term_ios();
+		ios.c_cc[VINTR] = 0x03; // ctrl+c
+		self.set_term_ios(ios);
+	}
+
+	pub fn disable_interrupt_key(&mut self) {
+		let mut ios = self.term_ios();
+		ios.c_cc[VINTR] = _POSIX_VDISABLE;
+		self.set_term_ios(ios);
+	}
+
+	pub fn mouse_protocol(&self) -> MouseProtocol {
+		self.mouse_protocol
+	}
+
+	pub fn set_mouse_protocol(&mut self, protocol: MouseProtocol) {
+		self.mouse_protocol = protocol;
+	}
+
+	pub fn interrupt_policy(&self) -> InterruptPolicy {
+		self.interrupt_policy
+	}
+
+	// Takes effect the next time raw mode and event listening start
+	pub fn set_interrupt_policy(&mut self, policy: InterruptPolicy) {
+		self.interrupt_policy = policy;
+	}
+
 	pub fn enable_mouse(&mut self) -> WriteResult {
 		self.csi("?1000h")?; // Enable VT200
 		self.csi("?1002h")?; // Enable "button" Xterm mouse events
-					 //self.csi("?1015h")?; // Enable urxvt extended mouse positions (for > 223 cells)
-		self.csi("?1006h")?; // Enable sgr extended mouse positions (for > 223 cells)
+		match self.mouse_protocol {
+			MouseProtocol::X10 => {}
+			MouseProtocol::Urxvt => {
+				self.csi("?1015h")?; // Enable urxvt extended mouse positions (for > 223 cells)
+			}
+			MouseProtocol::Sgr => {
+				self.csi("?1006h")?; // Enable sgr extended mouse positions (for > 223 cells)
+			}
+		}
 		Ok(())
 	}
 
 	pub fn disable_mouse(&mut self) -> WriteResult {
 		self.csi("?1006l")?; // Disable sgr extended mouse positions (for > 223 cells)
-					 //self.csi("?1015l")?; // Disble urxvt extended mouse positions (for > 223 cells)
+		self.csi("?1015l")?; // Disable urxvt extended mouse positions (for > 223 cells)
 		self.csi("?1002l")?; // Disable "button" Xterm mouse events
 		self.csi("?1000l")?; // Disable VT200
 		Ok(())
 	}
 
+	pub fn enable_bracketed_paste(&mut self) -> WriteResult {
+		self.csi("?2004h")?; // Wrap pasted text in ESC[200~ and ESC[201~
+		Ok(())
+	}
+
+	pub fn disable_bracketed_paste(&mut self) -> WriteResult {
+		self.csi("?2004l")?;
+		Ok(())
+	}
+
+	pub fn enable_focus_reporting(&mut self) -> WriteResult {
+		self.csi("?1004h")?; // Send ESC[I and ESC[O when the window gains or loses focus
+		Ok(())
+	}
+
+	pub fn disable_focus_reporting(&mut self) -> WriteResult {
+		self.csi("?1004l")?;
+		Ok(())
+	}
+
+	// The main and alt screens each have their own stack, this pushes onto the current one
+	pub fn enable_kitty_keyboard(&mut self, flags: KittyKeyboardFlags) -> WriteResult {
+		self.session.lock().unwrap().push_kitty_keyboard(flags);
+		self.csi(&format!(">{}u", flags.bits()))?; // Push flags onto the kitty keyboard stack
+		Ok(())
+	}
+
+	// Pops the flags last pushed on this screen, if we pushed any
+	pub fn disable_kitty_keyboard(&mut self) -> WriteResult {
+		if self.session.lock().unwrap().pop_kitty_keyboard() {
+			self.csi("<u")?; // Pop them back off
+		}
+		Ok(())
+	}
+
+	// Pops everything we pushed on this screen
+	pub fn reset_kitty_keyboard(&mut self) -> WriteResult {
+		let flags = self.session.lock().unwrap().take_kitty_keyboard();
+		self.write(&pop_kitty_keyboard_sequence(flags.len()))?;
+		Ok(())
+	}
+
+	// Pushes again whatever suspending popped off this screen
+	pub fn resume_kitty_keyboard(&mut self) -> WriteResult {
+		let flags = self.session.lock().unwrap().take_suspended_kitty_keyboard();
+		for flags in flags {
+			self.enable_kitty_keyboard(flags)?;
+		}
+		Ok(())
+	}
+
 	pub fn enable_mouse_move(&mut self) -> WriteResult {
 		self.csi("?1003h")?; // Enable "any" Xterm mouse events (i.e. mouse move without buttons)
 		Ok(())
@@ -0,0 +1,124 @@
+use super::fd::FdReader;
+use super::input::InputParser;
+use super::resize::WindowSize;
+use super::session::Session;
+use super::signals::SignalPipe;
+use super::suspend;
+use super::Terminal;
+use crate::Event;
+use libc::{SIGCONT, SIGTSTP, SIGWINCH};
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+// Reads input, resizes, Ctrl+Z and resumes on the caller's thread, for apps with their own event loop
+pub(crate) struct Poller {
+	in_fd: RawFd,
+	size: Arc<WindowSize>,
+	session: Arc<Mutex<Session>>,
+	parser: InputParser<FdReader>,
+	signals: SignalPipe,
+	suspend_id: u64,
+}
+
+impl Poller {
+	pub fn new(term: &Terminal) -> Result<Self, io::Error> {
+		let mut parser = InputParser::new(FdReader::new(term.in_fd));
+		parser.set_escape_timeout(term.escape_timeout);
+		parser.set_interrupt_policy(term.interrupt_policy);
+		// A lone ESC is left until escape_deadline, so reading never blocks
+		parser.set_defer_escape(true);
+		let signals = SignalPipe::new(&[SIGWINCH, SIGTSTP, SIGCONT])?;
+		Ok(Self {
+			in_fd: term.in_fd,
+			size: term.size.clone(),
+			session: term.session.clone(),
+			parser,
+			signals,
+			suspend_id: suspend::register(term.session.clone(), None)?,
+		})
+	}
+
+	pub fn fds(&self) -> Vec<RawFd> {
+		vec![self.in_fd, self.signals.fd()]
+	}
+
+	pub fn escape_deadline(&self) -> Option<Instant> {
+		self.parser.escape_deadline()
+	}
+
+	// Input has closed, there will be no more events
+	pub fn is_eof(&self) -> bool {
+		self.parser.is_eof()
+	}
+
+	pub fn read_events(&mut self) -> Vec<Event> {
+		let mut events = vec![];
+		let signals = self.signals.read_signals();
+		if signals.contains(&SIGWINCH) {
+			events.extend(self.size.update(self.in_fd));
+		}
+		if signals.contains(&SIGTSTP) {
+			// Returns once we've been continued
+			suspend::suspend_all();
+		}
+		if signals.contains(&SIGCONT) && self.session.lock().unwrap().resume() {
+			events.push(Event::Resumed);
+		}
+		while let Some(event) = self.parser.try_next_event() {
+			events.push(event);
+		}
+		events
+	}
+}
+
+impl Drop for Poller {
+	fn drop(&mut self) {
+		suspend::unregister(self.suspend_id);
+	}
+}
+
+impl Terminal {
+	// Start handling Ctrl+Z before the first read_events, which it has to wait for
+	pub fn start_polling(&mut self) -> Result<(), io::Error> {
+		self.poller()?;
+		Ok(())
+	}
+
+	// Wait for any of these to be readable, then call read_events
+	pub fn poll_fds(&mut self) -> Result<Vec<RawFd>, io::Error> {
+		Ok(self.poller()?.fds())
+	}
+
+	// Everything that has happened since the last call, without blocking. A lone ESC
+	// is held back in case it's the start of a sequence, so wait no later than
+	// escape_deadline before calling this again. Ctrl+Z suspends in here, returning
+	// Resumed once we're continued.
+	// Don't combine this with listen_for_events, they would fight over the input
+	pub fn read_events(&mut self) -> Result<Vec<Event>, io::Error> {
+		let poller = self.poller()?;
+		let events = poller.read_events();
+		if events.is_empty() && poller.is_eof() {
+			return Err(io::ErrorKind::UnexpectedEof.into());
+		}
+		Ok(events)
+	}
+
+	// When a held back lone ESC becomes the Esc key, if nothing else arrives first
+	pub fn escape_deadline(&self) -> Option<Instant> {
+		self.poller.as_ref().and_then(|poller| poller.escape_deadline())
+	}
+
+	pub fn stop_polling(&mut self) {
+		self.poller = None;
+	}
+
+	fn poller(&mut self) -> Result<&mut Poller, io::Error> {
+		let poller = match self.poller.take() {
+			Some(poller) => poller,
+			None => Poller::new(self)?,
+		};
+		Ok(self.poller.insert(poller))
+	}
+}
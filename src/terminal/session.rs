@@ -0,0 +1,311 @@
+use super::fd::FdWriter;
+use super::Terminal;
+use crate::KittyKeyboardFlags;
+use libc::{tcgetattr, tcsetattr, termios, TCSANOW};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::panic;
+use std::sync::{Arc, Mutex, PoisonError, TryLockError};
+use std::thread;
+
+// Undoes everything TerminalCanvas::attach turns on
+const RESTORE_SEQUENCE: &str =
+	"\x1b[?1003l\x1b[?1006l\x1b[?1015l\x1b[?1002l\x1b[?1000l\x1b[?2004l\x1b[?1004l\x1b[m\x1b[?25h";
+
+// Kitty keyboard flags we've pushed, which the main and alt screens each keep their own stack of
+#[derive(Default)]
+struct KittyKeyboardStacks {
+	main: Vec<KittyKeyboardFlags>,
+	alt: Vec<KittyKeyboardFlags>,
+}
+
+impl KittyKeyboardStacks {
+	fn screen(&mut self, alt_screen: bool) -> &mut Vec<KittyKeyboardFlags> {
+		if alt_screen {
+			&mut self.alt
+		} else {
+			&mut self.main
+		}
+	}
+}
+
+// Pops `count` entries off the current screen's stack, anything else there isn't ours
+pub(crate) fn pop_kitty_keyboard_sequence(count: usize) -> String {
+	match count {
+		0 => String::new(),
+		count => format!("\x1b[<{}u", count),
+	}
+}
+
+// What we need to put the terminal back how we found it, shared with guards
+// and the panic hook so they can restore it without the Terminal
+pub(crate) struct Session {
+	in_fd: RawFd,
+	out_fd: RawFd,
+	output: Arc<Mutex<BufWriter<FdWriter>>>,
+	original_ios: Option<termios>,
+	suspended_ios: Option<termios>,
+	alt_screen: bool,
+	kitty_keyboard: KittyKeyboardStacks,
+	// Popped while we're suspended, to be pushed again once we've resumed
+	suspended_kitty_keyboard: KittyKeyboardStacks,
+	// Keeps /dev/tty open when we opened it ourselves, for as long as any guard needs it.
+	// Dropped last so output is flushed first
+	tty: Option<File>,
+}
+
+impl Session {
+	pub fn new(in_fd: RawFd, out_fd: RawFd, output: Arc<Mutex<BufWriter<FdWriter>>>) -> Self {
+		Self {
+			in_fd,
+			out_fd,
+			output,
+			original_ios: None,
+			suspended_ios: None,
+			alt_screen: false,
+			kitty_keyboard: KittyKeyboardStacks::default(),
+			suspended_kitty_keyboard: KittyKeyboardStacks::default(),
+			tty: None,
+		}
+	}
+
+	pub fn set_tty(&mut self, tty: File) {
+		self.tty = Some(tty);
+	}
+
+	pub fn set_alt_screen(&mut self, alt_screen: bool) {
+		self.alt_screen = alt_screen;
+	}
+
+	pub fn push_kitty_keyboard(&mut self, flags: KittyKeyboardFlags) {
+		self.kitty_keyboard.screen(self.alt_screen).push(flags);
+	}
+
+	// Returns false if we haven't pushed anything on this screen
+	pub fn pop_kitty_keyboard(&mut self) -> bool {
+		self.kitty_keyboard.screen(self.alt_screen).pop().is_some()
+	}
+
+	// Everything we've pushed on this screen, for popping all at once
+	pub fn take_kitty_keyboard(&mut self) -> Vec<KittyKeyboardFlags> {
+		mem::take(self.kitty_keyboard.screen(self.alt_screen))
+	}
+
+	// What suspending popped off this screen
+	pub fn take_suspended_kitty_keyboard(&mut self) -> Vec<KittyKeyboardFlags> {
+		mem::take(self.suspended_kitty_keyboard.screen(self.alt_screen))
+	}
+
+	pub fn save_ios(&mut self, ios: termios) {
+		// Only the first snapshot is the user's original settings
+		if self.original_ios.is_none() {
+			self.original_ios = Some(ios);
+		}
+	}
+
+	pub fn is_attached(&self) -> bool {
+		self.original_ios.is_some()
+	}
+
+	pub fn take_ios(&mut self) -> Option<termios> {
+		self.original_ios.take()
+	}
+
+	pub fn restore(&mut self) {
+		if let Some(ios) = self.original_ios.take() {
+			self.write_restore_sequence();
+			self.kitty_keyboard = KittyKeyboardStacks::default();
+			self.suspended_kitty_keyboard = KittyKeyboardStacks::default();
+			unsafe {
+				tcsetattr(self.in_fd, TCSANOW, &ios);
+			}
+		}
+	}
+
+	// Hand the terminal back to the shell while we're stopped, keeping our own
+	// settings to put back when we continue
+	pub fn suspend(&mut self) {
+		if let Some(ios) = self.original_ios {
+			unsafe {
+				let mut current = std::mem::zeroed();
+				if tcgetattr(self.in_fd, &mut current) == 0 {
+					self.suspended_ios = Some(current);
+				}
+			}
+			self.write_restore_sequence();
+			self.suspended_kitty_keyboard = mem::take(&mut self.kitty_keyboard);
+			unsafe {
+				tcsetattr(self.in_fd, TCSANOW, &ios);
+			}
+		}
+	}
+
+	// Returns false if we're not attached, so there's nothing to resume
+	pub fn resume(&mut self) -> bool {
+		if let Some(ios) = self.suspended_ios.take() {
+			unsafe {
+				tcsetattr(self.in_fd, TCSANOW, &ios);
+			}
+		}
+		self.is_attached()
+	}
+
+	// Only pops the kitty keyboard flags we pushed, leaving the main screen as
+	// whoever started us set it up
+	fn restore_sequence(&self) -> String {
+		let mut sequence = RESTORE_SEQUENCE.to_string();
+		if self.alt_screen {
+			sequence += &pop_kitty_keyboard_sequence(self.kitty_keyboard.alt.len());
+		}
+		sequence += "\x1b[?1049l";
+		sequence += &pop_kitty_keyboard_sequence(self.kitty_keyboard.main.len());
+		sequence
+	}
+
+	// Whatever the Terminal has buffered goes out first, otherwise flushing it
+	// later could switch back to the alt screen after we've left it. The stacks
+	// and screen are recorded as they were left
+	fn write_restore_sequence(&mut self) {
+		let sequence = self.restore_sequence();
+		self.alt_screen = false;
+		let mut output = match self.output.try_lock() {
+			Ok(output) => output,
+			Err(TryLockError::Poisoned(err)) => err.into_inner(),
+			Err(TryLockError::WouldBlock) if thread::panicking() => {
+				// Panicked mid-write, so the lock will never be released. The buffered
+				// output is lost, but the terminal can still be restored
+				unsafe {
+					libc::write(self.out_fd, sequence.as_ptr() as *const _, sequence.len());
+				}
+				return;
+			}
+			Err(TryLockError::WouldBlock) => self.output.lock().unwrap_or_else(PoisonError::into_inner),
+		};
+		let _ = output.flush();
+		let _ = output.write_all(sequence.as_bytes());
+		let _ = output.flush();
+	}
+}
+
+fn restore_session(session: &Mutex<Session>) {
+	// Don't wait on the lock, we may be panicking while it's held
+	match session.try_lock() {
+		Ok(mut session) => session.restore(),
+		Err(TryLockError::Poisoned(err)) => err.into_inner().restore(),
+		Err(TryLockError::WouldBlock) => {}
+	}
+}
+
+// Restores the terminal when dropped, unless it was already detached
+#[must_use = "the terminal is restored as soon as the guard is dropped"]
+pub struct TerminalGuard {
+	session: Arc<Mutex<Session>>,
+}
+
+impl Drop for TerminalGuard {
+	fn drop(&mut self) {
+		restore_session(&self.session);
+	}
+}
+
+impl Terminal {
+	pub fn guard(&self) -> TerminalGuard {
+		TerminalGuard {
+			session: self.session.clone(),
+		}
+	}
+
+	// Restore the terminal before the panic message is printed, so it's readable
+	pub fn install_panic_hook(&self) {
+		let session = self.session.clone();
+		let next_hook = panic::take_hook();
+		panic::set_hook(Box::new(move |info| {
+			restore_session(&session);
+			next_hook(info);
+		}));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::super::fd::{pipe, FdReader};
+	use super::*;
+	use std::io::Read;
+
+	const FLAGS: KittyKeyboardFlags = KittyKeyboardFlags::DISAMBIGUATE_ESCAPE_CODES;
+
+	// Everything written by an attached Terminal on a pipe, up to and including the restore
+	fn written(run: impl FnOnce(&mut Terminal)) -> String {
+		let (read_fd, write_fd) = pipe().unwrap();
+		let mut term = Terminal::from_fd(read_fd, write_fd);
+		term.raw_mode();
+		let guard = term.guard();
+		run(&mut term);
+		drop(guard);
+		drop(term);
+
+		let mut output = String::new();
+		unsafe {
+			libc::close(write_fd);
+		}
+		FdReader::new(read_fd).read_to_string(&mut output).unwrap();
+		unsafe {
+			libc::close(read_fd);
+		}
+		output
+	}
+
+	#[test]
+	fn restore_pops_only_our_kitty_keyboard_flags() {
+		let output = written(|term| {
+			term.alt_screen().unwrap();
+		});
+		assert!(output.ends_with("\x1b[?25h\x1b[?1049l"), "{:?}", output);
+
+		let output = written(|term| {
+			term.alt_screen().unwrap();
+			term.enable_kitty_keyboard(FLAGS).unwrap();
+			term.enable_kitty_keyboard(FLAGS).unwrap();
+		});
+		assert!(output.ends_with("\x1b[?25h\x1b[<2u\x1b[?1049l"), "{:?}", output);
+
+		let output = written(|term| {
+			term.enable_kitty_keyboard(FLAGS).unwrap();
+			term.alt_screen().unwrap();
+			term.enable_kitty_keyboard(FLAGS).unwrap();
+			term.disable_kitty_keyboard().unwrap();
+			term.disable_kitty_keyboard().unwrap();
+		});
+		assert!(output.ends_with("\x1b[?25h\x1b[?1049l\x1b[<1u"), "{:?}", output);
+		assert_eq!(output.matches("\x1b[<u").count(), 1);
+	}
+
+	#[test]
+	fn resume_pushes_kitty_keyboard_flags_again() {
+		let output = written(|term| {
+			term.enable_kitty_keyboard(FLAGS).unwrap();
+			term.alt_screen().unwrap();
+			term.enable_kitty_keyboard(FLAGS).unwrap();
+			term.flush().unwrap();
+			term.session.lock().unwrap().suspend();
+			term.session.lock().unwrap().resume();
+			term.write("|").unwrap();
+
+			term.resume_kitty_keyboard().unwrap();
+			term.alt_screen().unwrap();
+			term.resume_kitty_keyboard().unwrap();
+			// Nothing more to push the second time
+			term.resume_kitty_keyboard().unwrap();
+		});
+		let (suspended, resumed) = output.split_once('|').unwrap();
+		assert!(suspended.ends_with("\x1b[<1u\x1b[?1049l\x1b[<1u"), "{:?}", suspended);
+		assert!(
+			resumed.starts_with("\x1b[>1u\x1b[?1049h\x1b[>1u\x1b[?1003l"),
+			"{:?}",
+			resumed
+		);
+		assert!(resumed.ends_with("\x1b[<1u\x1b[?1049l\x1b[<1u"), "{:?}", resumed);
+	}
+}
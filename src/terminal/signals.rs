@@ -0,0 +1,362 @@
+use super::fd::{pipe, poll_readable, StopPipe};
+use libc::{c_int, fcntl, sighandler_t, signal, F_GETFL, F_SETFL, O_NONBLOCK, SIGTSTP, SIG_DFL};
+use std::io;
+use std::iter;
+use std::os::unix::io::RawFd;
+use std::ptr;
+use std::sync::atomic::{AtomicI32, AtomicPtr, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+
+// Signal handlers can't lock or allocate, so each SignalPipe claims a node in this
+// list holding its write fd and a bitmask of the signals it wants. Nodes are never
+// freed, a dropped pipe's node is left for the next one to reuse
+struct PipeNode {
+	fd: AtomicI32,
+	signals: AtomicU64,
+	next: AtomicPtr<PipeNode>,
+}
+
+static PIPES: AtomicPtr<PipeNode> = AtomicPtr::new(ptr::null_mut());
+
+fn pipe_nodes() -> impl Iterator<Item = &'static PipeNode> {
+	let mut node = PIPES.load(Ordering::SeqCst);
+	iter::from_fn(move || {
+		let current = unsafe { node.as_ref() }?;
+		node = current.next.load(Ordering::SeqCst);
+		Some(current)
+	})
+}
+
+fn claim_node(fd: RawFd) -> &'static PipeNode {
+	let free = pipe_nodes().find(|node| {
+		node.fd
+			.compare_exchange(-1, fd, Ordering::SeqCst, Ordering::SeqCst)
+			.is_ok()
+	});
+	if let Some(node) = free {
+		return node;
+	}
+	let node = Box::leak(Box::new(PipeNode {
+		fd: AtomicI32::new(fd),
+		signals: AtomicU64::new(0),
+		next: AtomicPtr::new(PIPES.load(Ordering::SeqCst)),
+	}));
+	while let Err(head) = PIPES.compare_exchange(
+		node.next.load(Ordering::SeqCst),
+		node,
+		Ordering::SeqCst,
+		Ordering::SeqCst,
+	) {
+		node.next.store(head, Ordering::SeqCst);
+	}
+	node
+}
+
+// Each SIGTSTP reaches every pipe that wants it, this tells them whether it's been handled yet
+static SUSPENDS: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn suspends_received() -> u64 {
+	SUSPENDS.load(Ordering::SeqCst)
+}
+
+extern "C" fn handle_signal(sig: c_int) {
+	let errno = io::Error::last_os_error().raw_os_error().unwrap_or(0);
+	if sig == SIGTSTP {
+		SUSPENDS.fetch_add(1, Ordering::SeqCst);
+	}
+	for node in pipe_nodes() {
+		let fd = node.fd.load(Ordering::SeqCst);
+		if fd >= 0 && node.signals.load(Ordering::SeqCst) & (1 << sig) != 0 {
+			let byte = sig as u8;
+			unsafe {
+				libc::write(fd, &byte as *const u8 as *const _, 1);
+			}
+		}
+	}
+	set_errno(errno);
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_errno(errno: c_int) {
+	unsafe {
+		*libc::__errno_location() = errno;
+	}
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+fn set_errno(errno: c_int) {
+	unsafe {
+		*libc::__error() = errno;
+	}
+}
+
+#[cfg(not(any(
+	target_os = "linux",
+	target_os = "android",
+	target_os = "macos",
+	target_os = "ios",
+	target_os = "freebsd"
+)))]
+fn set_errno(_errno: c_int) {}
+
+fn set_nonblocking(fd: RawFd) {
+	unsafe {
+		let flags = fcntl(fd, F_GETFL);
+		fcntl(fd, F_SETFL, flags | O_NONBLOCK);
+	}
+}
+
+fn mask(signals: &[c_int]) -> u64 {
+	signals.iter().fold(0, |mask, &sig| mask | (1 << sig))
+}
+
+fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+	mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Signals our handler is installed for, which go back to the default once no pipe wants them
+static INSTALLED: Mutex<u64> = Mutex::new(0);
+
+fn update_handlers() {
+	let mut installed = lock(&INSTALLED);
+	let wanted = pipe_nodes()
+		.filter(|node| node.fd.load(Ordering::SeqCst) >= 0)
+		.fold(0, |mask, node| mask | node.signals.load(Ordering::SeqCst));
+	for sig in 1..64 {
+		let bit = 1 << sig;
+		if wanted & bit != 0 && *installed & bit == 0 {
+			unsafe {
+				signal(sig, handle_signal as extern "C" fn(c_int) as sighandler_t);
+			}
+		} else if wanted & bit == 0 && *installed & bit != 0 {
+			unsafe {
+				signal(sig, SIG_DFL);
+			}
+		}
+	}
+	*installed = wanted;
+}
+
+// Put our handler back after it was temporarily replaced, if a pipe still wants the signal
+pub(crate) fn install_handler(sig: c_int) {
+	if *lock(&INSTALLED) & (1 << sig) != 0 {
+		unsafe {
+			signal(sig, handle_signal as extern "C" fn(c_int) as sighandler_t);
+		}
+	}
+}
+
+// Turns signals into bytes we can read or poll for, see "the self-pipe trick"
+pub(crate) struct SignalPipe {
+	node: &'static PipeNode,
+	read_fd: RawFd,
+	write_fd: RawFd,
+}
+
+impl SignalPipe {
+	pub fn new(signals: &[c_int]) -> Result<Self, io::Error> {
+		let (read_fd, write_fd) = pipe()?;
+		set_nonblocking(read_fd);
+		set_nonblocking(write_fd);
+
+		let pipe = Self {
+			node: claim_node(write_fd),
+			read_fd,
+			write_fd,
+		};
+		pipe.set_signals(mask(signals));
+		Ok(pipe)
+	}
+
+	fn set_signals(&self, mask: u64) {
+		self.node.signals.store(mask, Ordering::SeqCst);
+		update_handlers();
+	}
+
+	pub fn fd(&self) -> RawFd {
+		self.read_fd
+	}
+
+	// Signals received since we last checked, without blocking
+	pub fn read_signals(&self) -> Vec<c_int> {
+		let mut bytes = [0u8; 32];
+		let mut signals = vec![];
+		loop {
+			let len = unsafe { libc::read(self.read_fd, bytes.as_mut_ptr() as *mut _, bytes.len()) };
+			if len <= 0 {
+				return signals;
+			}
+			signals.extend(bytes[..len as usize].iter().map(|&sig| sig as c_int));
+		}
+	}
+
+	// Block until at least one signal arrives, or return None once `stop_fd` is readable
+	pub fn wait_until_stopped(&self, stop_fd: RawFd) -> Option<Vec<c_int>> {
+		loop {
+			if poll_readable(&[self.read_fd, stop_fd], None).contains(&stop_fd) {
+				return None;
+			}
+			let signals = self.read_signals();
+			if !signals.is_empty() {
+				return Some(signals);
+			}
+		}
+	}
+}
+
+impl Drop for SignalPipe {
+	fn drop(&mut self) {
+		self.node.signals.store(0, Ordering::SeqCst);
+		self.node.fd.store(-1, Ordering::SeqCst);
+		update_handlers();
+		unsafe {
+			libc::close(self.read_fd);
+			libc::close(self.write_fd);
+		}
+	}
+}
+
+type Handler = Arc<Mutex<dyn FnMut(c_int) + Send>>;
+
+struct Subscriber {
+	id: u64,
+	mask: u64,
+	handler: Handler,
+}
+
+// Kept apart from DISPATCHER so the thread never needs that lock, and can be joined while it's held
+static SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(Vec::new());
+
+// One thread handles signals for every subscriber, started by the first and stopped with the last
+struct Dispatcher {
+	next_id: u64,
+	thread: Option<DispatchThread>,
+}
+
+static DISPATCHER: Mutex<Dispatcher> = Mutex::new(Dispatcher {
+	next_id: 0,
+	thread: None,
+});
+
+struct DispatchThread {
+	pipe: Arc<SignalPipe>,
+	handle: JoinHandle<()>,
+	stop: StopPipe,
+}
+
+impl DispatchThread {
+	fn spawn() -> Result<Self, io::Error> {
+		let pipe = Arc::new(SignalPipe::new(&[])?);
+		let stop = StopPipe::new()?;
+		let stop_fd = stop.fd();
+		let reader = pipe.clone();
+		let handle = thread::spawn(move || {
+			while let Some(signals) = reader.wait_until_stopped(stop_fd) {
+				// Called without the lock, so handlers can take locks held by code that's subscribing
+				let subscribers: Vec<(u64, Handler)> = lock(&SUBSCRIBERS)
+					.iter()
+					.map(|subscriber| (subscriber.mask, subscriber.handler.clone()))
+					.collect();
+				for sig in signals {
+					for (mask, handler) in &subscribers {
+						if mask & (1 << sig) != 0 {
+							let mut handler = lock(handler);
+							handler(sig);
+						}
+					}
+				}
+			}
+		});
+		Ok(Self { pipe, handle, stop })
+	}
+
+	fn update_signals(&self) {
+		let mask = lock(&SUBSCRIBERS)
+			.iter()
+			.fold(0, |mask, subscriber| mask | subscriber.mask);
+		self.pipe.set_signals(mask);
+	}
+
+	fn stop(self) {
+		self.stop.stop();
+		let _ = self.handle.join();
+	}
+}
+
+// Call `handler` on the shared signal thread whenever one of `signals` arrives,
+// until the returned Subscription is dropped
+pub(crate) fn subscribe<F>(signals: &[c_int], handler: F) -> Result<Subscription, io::Error>
+where
+	F: FnMut(c_int) + Send + 'static,
+{
+	let mut dispatcher = lock(&DISPATCHER);
+	if dispatcher.thread.is_none() {
+		dispatcher.thread = Some(DispatchThread::spawn()?);
+	}
+	let id = dispatcher.next_id;
+	dispatcher.next_id += 1;
+	lock(&SUBSCRIBERS).push(Subscriber {
+		id,
+		mask: mask(signals),
+		handler: Arc::new(Mutex::new(handler)),
+	});
+	if let Some(thread) = &dispatcher.thread {
+		thread.update_signals();
+	}
+	Ok(Subscription { id })
+}
+
+pub(crate) struct Subscription {
+	id: u64,
+}
+
+impl Drop for Subscription {
+	fn drop(&mut self) {
+		let mut dispatcher = lock(&DISPATCHER);
+		let mut subscribers = lock(&SUBSCRIBERS);
+		subscribers.retain(|subscriber| subscriber.id != self.id);
+		let last = subscribers.is_empty();
+		drop(subscribers);
+		if last {
+			if let Some(thread) = dispatcher.thread.take() {
+				thread.stop();
+			}
+		} else if let Some(thread) = &dispatcher.thread {
+			thread.update_signals();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use libc::SIGUSR2;
+	use std::sync::atomic::AtomicUsize;
+	use std::time::{Duration, Instant};
+
+	#[test]
+	fn subscriptions_share_one_thread() {
+		static CALLS: AtomicUsize = AtomicUsize::new(0);
+		let subscriptions: Vec<_> = (0..40)
+			.map(|_| {
+				subscribe(&[SIGUSR2], |_| {
+					CALLS.fetch_add(1, Ordering::SeqCst);
+				})
+				.unwrap()
+			})
+			.collect();
+
+		unsafe {
+			libc::raise(SIGUSR2);
+		}
+		let start = Instant::now();
+		while CALLS.load(Ordering::SeqCst) < 40 && start.elapsed() < Duration::from_secs(1) {
+			thread::sleep(Duration::from_millis(1));
+		}
+		assert_eq!(CALLS.load(Ordering::SeqCst), 40);
+
+		drop(subscriptions);
+		assert!(lock(&DISPATCHER).thread.is_none());
+	}
+}
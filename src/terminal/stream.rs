@@ -0,0 +1,225 @@
+use super::fd::{poll_readable, StopPipe};
+use super::polling::Poller;
+use super::Terminal;
+use crate::Event;
+use futures_core::Stream;
+use std::collections::VecDeque;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+struct Wakeup {
+	// The stream is waiting on the fds
+	armed: bool,
+	stopped: bool,
+	waker: Option<Waker>,
+	// Wake up then even without input, so a lone ESC can become the Esc key
+	deadline: Option<Instant>,
+}
+
+// Input and resize events as a Stream, so they can be awaited alongside anything else.
+// Reads never block, a helper thread only sleeps in poll(2) until there's something
+// to read and then wakes the task
+pub struct EventStream {
+	poller: Poller,
+	events: VecDeque<Event>,
+	wakeup: Arc<(Mutex<Wakeup>, Condvar)>,
+	stop: StopPipe,
+	waiter: Option<JoinHandle<()>>,
+}
+
+impl EventStream {
+	fn new(poller: Poller) -> Result<Self, io::Error> {
+		let stop = StopPipe::new()?;
+		let wakeup = Arc::new((Mutex::new(Wakeup::default()), Condvar::new()));
+
+		let mut fds = poller.fds();
+		fds.push(stop.fd());
+		let waiter = {
+			let wakeup = wakeup.clone();
+			thread::spawn(move || wait_for_input(&fds, &wakeup))
+		};
+
+		Ok(Self {
+			poller,
+			events: VecDeque::new(),
+			wakeup,
+			stop,
+			waiter: Some(waiter),
+		})
+	}
+}
+
+fn wait_for_input(fds: &[RawFd], wakeup: &(Mutex<Wakeup>, Condvar)) {
+	let (state, armed) = wakeup;
+	let stop_fd = fds[fds.len() - 1];
+	loop {
+		let deadline = {
+			let mut state = state.lock().unwrap();
+			while !state.armed && !state.stopped {
+				state = armed.wait(state).unwrap();
+			}
+			if state.stopped {
+				return;
+			}
+			state.deadline
+		};
+
+		// poll(2) only has millisecond resolution, so round up to be sure the deadline has passed
+		let timeout =
+			deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()) + Duration::from_millis(1));
+		if poll_readable(fds, timeout).contains(&stop_fd) {
+			return;
+		}
+
+		let mut state = state.lock().unwrap();
+		state.armed = false;
+		if let Some(waker) = state.waker.take() {
+			waker.wake();
+		}
+	}
+}
+
+impl Stream for EventStream {
+	type Item = Event;
+
+	fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+		let this = self.get_mut();
+		if this.events.is_empty() {
+			this.events.extend(this.poller.read_events());
+		}
+		if let Some(event) = this.events.pop_front() {
+			return Poll::Ready(Some(event));
+		}
+		if this.poller.is_eof() {
+			return Poll::Ready(None);
+		}
+
+		let (state, armed) = &*this.wakeup;
+		let mut state = state.lock().unwrap();
+		state.waker = Some(cx.waker().clone());
+		state.deadline = this.poller.escape_deadline();
+		state.armed = true;
+		armed.notify_one();
+
+		Poll::Pending
+	}
+}
+
+impl Drop for EventStream {
+	fn drop(&mut self) {
+		let (state, armed) = &*self.wakeup;
+		state.lock().unwrap().stopped = true;
+		armed.notify_one();
+		self.stop.stop();
+		if let Some(waiter) = self.waiter.take() {
+			let _ = waiter.join();
+		}
+	}
+}
+
+impl Terminal {
+	// Takes over from read_events, if it was being used.
+	// Don't combine this with listen_for_events, they would fight over the input
+	pub fn event_stream(&mut self) -> Result<EventStream, io::Error> {
+		let poller = match self.poller.take() {
+			Some(poller) => poller,
+			None => Poller::new(self)?,
+		};
+		EventStream::new(poller)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{Modifiers, TerminalCanvas};
+	use std::ffi::CStr;
+	use std::task::Wake;
+	use std::thread::Thread;
+
+	struct Unpark(Thread);
+
+	impl Wake for Unpark {
+		fn wake(self: Arc<Self>) {
+			self.0.unpark();
+		}
+	}
+
+	// Just enough of an executor to wait on one stream
+	fn next_event(stream: &mut EventStream) -> Option<Event> {
+		let waker = Waker::from(Arc::new(Unpark(thread::current())));
+		let mut cx = Context::from_waker(&waker);
+		let start = Instant::now();
+		loop {
+			match Pin::new(&mut *stream).poll_next(&mut cx) {
+				Poll::Ready(event) => return event,
+				Poll::Pending => thread::park_timeout(Duration::from_millis(100)),
+			}
+			assert!(start.elapsed() < Duration::from_secs(5), "no event");
+		}
+	}
+
+	fn open_pty() -> (RawFd, RawFd) {
+		unsafe {
+			let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+			assert!(master >= 0);
+			libc::grantpt(master);
+			libc::unlockpt(master);
+			let name = CStr::from_ptr(libc::ptsname(master));
+			let slave = libc::open(name.as_ptr(), libc::O_RDWR | libc::O_NOCTTY);
+			assert!(slave >= 0);
+			(master, slave)
+		}
+	}
+
+	// Whatever has been written to the pty so far
+	fn read_output(master: RawFd) -> String {
+		let mut output = vec![];
+		let mut buffer = [0u8; 4096];
+		while !poll_readable(&[master], Some(Duration::from_millis(50))).is_empty() {
+			let len = unsafe { libc::read(master, buffer.as_mut_ptr() as *mut _, buffer.len()) };
+			if len <= 0 {
+				break;
+			}
+			output.extend_from_slice(&buffer[..len as usize]);
+		}
+		String::from_utf8_lossy(&output).into_owned()
+	}
+
+	#[test]
+	fn resumed_through_handle_event() {
+		let (master, slave) = open_pty();
+		let mut canvas = TerminalCanvas::with_terminal(Terminal::from_fd(slave, slave));
+		let guard = canvas.attach_polling().unwrap();
+		let mut stream = canvas.event_stream().unwrap();
+		assert!(read_output(master).contains("\x1b[?1049h"));
+
+		unsafe {
+			libc::write(master, b"a".as_ptr() as *const _, 1);
+		}
+		assert_eq!(next_event(&mut stream), Some(Event::KeyPress('a', Modifiers::empty())));
+
+		unsafe {
+			libc::kill(libc::getpid(), libc::SIGCONT);
+		}
+		let event = next_event(&mut stream).unwrap();
+		assert_eq!(event, Event::Resumed);
+		canvas.handle_event(event);
+		assert!(read_output(master).contains("\x1b[?1049h"));
+		assert!(canvas.take_resume_error().is_none());
+
+		drop(stream);
+		drop(guard);
+		drop(canvas);
+		unsafe {
+			libc::close(slave);
+			libc::close(master);
+		}
+	}
+}
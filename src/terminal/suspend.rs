@@ -0,0 +1,130 @@
+use super::events::broadcast;
+use super::session::Session;
+use super::signals::{install_handler, subscribe, suspends_received, Subscription};
+use super::Terminal;
+use crate::Event;
+use libc::{c_int, raise, signal, SIGCONT, SIGTSTP, SIG_DFL};
+use std::io;
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, PoisonError};
+
+struct SuspendListener {
+	id: u64,
+	session: Arc<Mutex<Session>>,
+	// None when a Poller reads the signals itself, rather than the signal thread
+	listeners: Option<Arc<Mutex<Vec<mpsc::Sender<Event>>>>>,
+}
+
+// Ctrl+Z stops the whole process, so every Terminal is restored and then the
+// process is stopped once, by whichever reader sees it first
+struct Suspender {
+	terminals: Vec<SuspendListener>,
+	next_id: u64,
+	handled_suspends: u64,
+	subscription: Option<Subscription>,
+}
+
+static SUSPENDER: Mutex<Suspender> = Mutex::new(Suspender {
+	terminals: Vec::new(),
+	next_id: 0,
+	handled_suspends: 0,
+	subscription: None,
+});
+
+fn suspender() -> MutexGuard<'static, Suspender> {
+	SUSPENDER.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn handle_signal(sig: c_int) {
+	match sig {
+		SIGTSTP => suspend_all(),
+		SIGCONT => {
+			for terminal in &suspender().terminals {
+				if let Some(listeners) = &terminal.listeners {
+					if terminal.session.lock().unwrap().resume() {
+						broadcast(listeners, Event::Resumed);
+					}
+				}
+			}
+		}
+		_ => {}
+	}
+}
+
+// Called for every SIGTSTP a reader sees, but only acts on ones nobody has handled yet
+pub(crate) fn suspend_all() {
+	let mut suspender = suspender();
+	let received = suspends_received();
+	if suspender.handled_suspends == received {
+		return;
+	}
+	suspender.handled_suspends = received;
+
+	let mut suspended: Vec<&Arc<Mutex<Session>>> = vec![];
+	for terminal in &suspender.terminals {
+		if !suspended.iter().any(|session| Arc::ptr_eq(session, &terminal.session)) {
+			terminal.session.lock().unwrap().suspend();
+			suspended.push(&terminal.session);
+		}
+	}
+	stop_process();
+}
+
+pub(crate) fn register(
+	session: Arc<Mutex<Session>>,
+	listeners: Option<Arc<Mutex<Vec<mpsc::Sender<Event>>>>>,
+) -> Result<u64, io::Error> {
+	let mut suspender = suspender();
+	if listeners.is_some() && suspender.subscription.is_none() {
+		suspender.subscription = Some(subscribe(&[SIGTSTP, SIGCONT], handle_signal)?);
+	}
+	let id = suspender.next_id;
+	suspender.next_id += 1;
+	suspender.terminals.push(SuspendListener { id, session, listeners });
+	Ok(id)
+}
+
+pub(crate) fn unregister(id: u64) {
+	let subscription = {
+		let mut suspender = suspender();
+		suspender.terminals.retain(|terminal| terminal.id != id);
+		if suspender.terminals.iter().all(|terminal| terminal.listeners.is_none()) {
+			suspender.subscription.take()
+		} else {
+			None
+		}
+	};
+	// Dropped after letting go of the lock, which the signal thread may be waiting on
+	drop(subscription);
+}
+
+impl Terminal {
+	// Give the terminal back to the shell on Ctrl+Z, and take it again when we're continued
+	pub fn listen_for_suspend(&mut self) -> Result<(), io::Error> {
+		if self.suspend_id.is_none() {
+			self.suspend_id = Some(register(self.session.clone(), Some(self.event_channels.clone()))?);
+		}
+		Ok(())
+	}
+
+	pub(crate) fn stop_listening_for_suspend(&mut self) {
+		if let Some(id) = self.suspend_id.take() {
+			unregister(id);
+		}
+	}
+
+	// Same as pressing Ctrl+Z, for when ISIG is off and it arrives as a key press instead
+	pub fn suspend(&self) {
+		unsafe {
+			raise(SIGTSTP);
+		}
+	}
+}
+
+fn stop_process() {
+	unsafe {
+		signal(SIGTSTP, SIG_DFL);
+		raise(SIGTSTP);
+	}
+	// Execution continues here after SIGCONT
+	install_handler(SIGTSTP);
+}
@@ -1,5 +1,19 @@
 use super::Terminal;
+use std::io;
+use std::time::Duration;
 
 impl Terminal {
-	pub fn listen_for_events(&mut self) {}
+	pub fn escape_timeout(&self) -> Duration {
+		self.escape_timeout
+	}
+
+	pub fn set_escape_timeout(&mut self, timeout: Duration) {
+		self.escape_timeout = timeout;
+	}
+
+	pub fn listen_for_events(&mut self) -> Result<(), io::Error> {
+		Ok(())
+	}
+
+	pub fn stop_listening(&mut self) {}
 }
@@ -1,28 +1,50 @@
-use crate::{Color, Event};
+use crate::{Attributes, Color, ColorDepth, Event, InterruptPolicy, Link, MouseProtocol, UnderlineStyle};
 use std::io;
 use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
 
 mod events;
 mod flags;
 mod input;
 mod modes;
+mod polling;
 mod resize;
+mod session;
+mod suspend;
+
+pub use session::TerminalGuard;
 
 pub type WriteResult = Result<usize, io::Error>;
 
 pub struct Terminal {
 	event_channels: Arc<Mutex<Vec<mpsc::Sender<Event>>>>,
+	mouse_protocol: MouseProtocol,
+	interrupt_policy: InterruptPolicy,
+	escape_timeout: Duration,
+	color_depth: ColorDepth,
+	styled_underlines: bool,
 	width: u32,
 	height: u32,
 	font_width: u32,
 	font_height: u32,
 }
 
+impl Default for Terminal {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
 impl Terminal {
 	pub fn new() -> Self {
 		let listeners: Arc<Mutex<Vec<mpsc::Sender<Event>>>> = Arc::new(Mutex::new(vec![]));
 		Self {
 			event_channels: listeners.clone(),
+			mouse_protocol: MouseProtocol::default(),
+			interrupt_policy: InterruptPolicy::default(),
+			escape_timeout: Duration::from_millis(50),
+			color_depth: ColorDepth::default(),
+			styled_underlines: true,
 			width: 120,
 			height: 48,
 			font_width: 16,
@@ -30,6 +52,10 @@ impl Terminal {
 		}
 	}
 
+	pub fn open_tty() -> Result<Self, io::Error> {
+		Ok(Self::new())
+	}
+
 	pub fn size(&self) -> (u32, u32) {
 		(self.width(), self.height())
 	}
@@ -67,6 +93,10 @@ impl Terminal {
 		self.csi(&format!("{};{}H", y + 1, x + 1))
 	}
 
+	pub fn hyperlink(&mut self, _link: Option<Link>) -> WriteResult {
+		Ok(0)
+	}
+
 	pub fn show_cursor(&mut self) -> WriteResult {
 		self.csi("?25h")
 	}
@@ -75,14 +105,35 @@ impl Terminal {
 		self.csi("?25l")
 	}
 
+	pub fn color_depth(&self) -> ColorDepth {
+		self.color_depth
+	}
+
+	pub fn set_color_depth(&mut self, depth: ColorDepth) {
+		self.color_depth = depth;
+	}
+
+	pub fn styled_underlines(&self) -> bool {
+		self.styled_underlines
+	}
+
+	pub fn set_styled_underlines(&mut self, supported: bool) {
+		self.styled_underlines = supported;
+	}
+
 	pub fn fg_color(&mut self, color: Color) -> WriteResult {
-		let (r, g, b) = color.as_rgb();
-		self.csi(&format!("38;2;{};{};{}m", r, g, b))
+		self.sgr_color(38, color)
 	}
 
 	pub fn bg_color(&mut self, color: Color) -> WriteResult {
-		let (r, g, b) = color.as_rgb();
-		self.csi(&format!("48;2;{};{};{}m", r, g, b))
+		self.sgr_color(48, color)
+	}
+
+	fn sgr_color(&mut self, code: u8, color: Color) -> WriteResult {
+		match self.color_depth.sgr_color(code, color) {
+			Some(params) => self.csi(&format!("{}m", params)),
+			None => Ok(0),
+		}
 	}
 
 	pub fn fg_8bit_color(&mut self, color: Color) -> WriteResult {
@@ -102,4 +153,24 @@ impl Terminal {
 	pub fn reset(&mut self) -> WriteResult {
 		self.csi("m")
 	}
+
+	pub fn set_attributes(&mut self, _attrs: Attributes) -> WriteResult {
+		Ok(0)
+	}
+
+	pub fn unset_attributes(&mut self, _attrs: Attributes) -> WriteResult {
+		Ok(0)
+	}
+
+	pub fn underline_style(&mut self, _style: UnderlineStyle) -> WriteResult {
+		Ok(0)
+	}
+
+	pub fn underline_color(&mut self, _color: Color) -> WriteResult {
+		Ok(0)
+	}
+
+	pub fn default_underline_color(&mut self) -> WriteResult {
+		Ok(0)
+	}
 }
@@ -1,4 +1,5 @@
 use super::Terminal;
+use crate::{InterruptPolicy, KittyKeyboardFlags, MouseProtocol};
 use std::io;
 
 type WriteResult = Result<(), io::Error>;
@@ -6,6 +7,7 @@ type WriteResult = Result<(), io::Error>;
 impl Terminal {
 	pub fn normal_mode(&mut self) {}
 	pub fn raw_mode(&mut self) {}
+	pub fn full_raw_mode(&mut self) {}
 	pub fn enable_echo(&mut self) {}
 	pub fn disable_echo(&mut self) {}
 	pub fn enable_canonical(&mut self) {}
@@ -18,12 +20,50 @@ impl Terminal {
 	pub fn disable_cr_to_nl(&mut self) {}
 	pub fn enable_ctrl_c(&mut self) {}
 	pub fn disable_ctrl_c(&mut self) {}
+	pub fn enable_interrupt_key(&mut self) {}
+	pub fn disable_interrupt_key(&mut self) {}
+	pub fn interrupt_policy(&self) -> InterruptPolicy {
+		self.interrupt_policy
+	}
+	pub fn set_interrupt_policy(&mut self, policy: InterruptPolicy) {
+		self.interrupt_policy = policy;
+	}
+	pub fn mouse_protocol(&self) -> MouseProtocol {
+		self.mouse_protocol
+	}
+	pub fn set_mouse_protocol(&mut self, protocol: MouseProtocol) {
+		self.mouse_protocol = protocol;
+	}
 	pub fn enable_mouse(&mut self) -> WriteResult {
 		Ok(())
 	}
 	pub fn disable_mouse(&mut self) -> WriteResult {
 		Ok(())
 	}
+	pub fn enable_bracketed_paste(&mut self) -> WriteResult {
+		Ok(())
+	}
+	pub fn disable_bracketed_paste(&mut self) -> WriteResult {
+		Ok(())
+	}
+	pub fn enable_focus_reporting(&mut self) -> WriteResult {
+		Ok(())
+	}
+	pub fn disable_focus_reporting(&mut self) -> WriteResult {
+		Ok(())
+	}
+	pub fn enable_kitty_keyboard(&mut self, _flags: KittyKeyboardFlags) -> WriteResult {
+		Ok(())
+	}
+	pub fn disable_kitty_keyboard(&mut self) -> WriteResult {
+		Ok(())
+	}
+	pub fn reset_kitty_keyboard(&mut self) -> WriteResult {
+		Ok(())
+	}
+	pub fn resume_kitty_keyboard(&mut self) -> WriteResult {
+		Ok(())
+	}
 	pub fn enable_mouse_move(&mut self) -> WriteResult {
 		Ok(())
 	}
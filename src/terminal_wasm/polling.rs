@@ -0,0 +1,17 @@
+use super::Terminal;
+use crate::Event;
+use std::io;
+use std::time::Instant;
+
+impl Terminal {
+	pub fn start_polling(&mut self) -> Result<(), io::Error> {
+		Ok(())
+	}
+	pub fn escape_deadline(&self) -> Option<Instant> {
+		None
+	}
+	pub fn read_events(&mut self) -> Result<Vec<Event>, io::Error> {
+		Ok(vec![])
+	}
+	pub fn stop_polling(&mut self) {}
+}
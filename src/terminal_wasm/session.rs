@@ -0,0 +1,11 @@
+use super::Terminal;
+
+#[must_use = "the terminal is restored as soon as the guard is dropped"]
+pub struct TerminalGuard;
+
+impl Terminal {
+	pub fn guard(&self) -> TerminalGuard {
+		TerminalGuard
+	}
+	pub fn install_panic_hook(&self) {}
+}
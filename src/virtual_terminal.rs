@@ -0,0 +1,636 @@
+use crate::{
+	Attributes, Backend, BackendResult, Canvas, Cell, Color, ColorDepth, Link, Symbol, UnderlineStyle, WriteBackend,
+};
+use std::io::{self, Write};
+use unicode_width::UnicodeWidthChar;
+
+const ESC: u8 = 0x1b;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ParseState {
+	Ground,
+	Escape,
+	Csi,
+	Osc,
+	// Saw an ESC inside an OSC, which should be the start of the terminator
+	OscEscape,
+}
+
+// A headless terminal that interprets the escape sequences a Terminal emits,
+// so you can inspect exactly what would end up on screen
+#[derive(Debug, Clone)]
+pub struct VirtualTerminal {
+	canvas: Canvas,
+	cursor_x: u32,
+	cursor_y: u32,
+	fg: Color,
+	bg: Color,
+	attrs: Attributes,
+	underline_style: UnderlineStyle,
+	underline_color: Option<Color>,
+	link: Option<Link>,
+	color_depth: ColorDepth,
+	styled_underlines: bool,
+	state: ParseState,
+	params: String,
+	osc: Vec<u8>,
+	utf8: Vec<u8>,
+}
+
+impl VirtualTerminal {
+	pub fn new(width: u32, height: u32) -> Self {
+		Self {
+			canvas: Canvas::new(width, height),
+			cursor_x: 0,
+			cursor_y: 0,
+			fg: Color::transparent(),
+			bg: Color::transparent(),
+			attrs: Attributes::empty(),
+			underline_style: UnderlineStyle::Single,
+			underline_color: None,
+			link: None,
+			color_depth: ColorDepth::default(),
+			styled_underlines: true,
+			state: ParseState::Ground,
+			params: String::new(),
+			osc: vec![],
+			utf8: vec![],
+		}
+	}
+
+	pub fn canvas(&self) -> &Canvas {
+		&self.canvas
+	}
+
+	pub fn cell(&self, x: i32, y: i32) -> Option<&Cell> {
+		self.canvas.cell(x, y)
+	}
+
+	pub fn cursor(&self) -> (u32, u32) {
+		(self.cursor_x, self.cursor_y)
+	}
+
+	pub fn color_depth(&self) -> ColorDepth {
+		self.color_depth
+	}
+
+	// Emit colors as a terminal with this depth would receive them
+	pub fn set_color_depth(&mut self, depth: ColorDepth) {
+		self.color_depth = depth;
+	}
+
+	pub fn styled_underlines(&self) -> bool {
+		self.styled_underlines
+	}
+
+	// Emit underlines as a terminal without styled underlines would receive them
+	pub fn set_styled_underlines(&mut self, supported: bool) {
+		self.styled_underlines = supported;
+	}
+
+	// Backend calls are formatted by a WriteBackend and parsed straight back in,
+	// so the escapes are the same as any other output gets
+	fn escapes(&mut self) -> WriteBackend<&mut Self> {
+		let (width, height) = self.canvas.size();
+		let depth = self.color_depth;
+		let styled_underlines = self.styled_underlines;
+		let mut backend = WriteBackend::new(self, width, height);
+		backend.set_color_depth(depth);
+		backend.set_styled_underlines(styled_underlines);
+		backend
+	}
+
+	pub fn resize(&mut self, width: u32, height: u32) {
+		self.canvas.resize(width, height);
+		self.cursor_x = self.cursor_x.min(width.saturating_sub(1));
+		self.cursor_y = self.cursor_y.min(height.saturating_sub(1));
+	}
+
+	pub fn line(&self, y: u32) -> String {
+		(0..self.canvas.width())
+			.filter_map(|x| self.canvas.cell(x as i32, y as i32))
+			.map(|cell| cell.symbol.to_string())
+			.collect()
+	}
+
+	pub fn text(&self) -> String {
+		(0..self.canvas.height())
+			.map(|y| self.line(y))
+			.collect::<Vec<_>>()
+			.join("\n")
+	}
+
+	fn put_char(&mut self, c: char) {
+		let width = c.width().unwrap_or(1) as u32;
+		if width == 0 || self.previous_symbol().to_string().ends_with('\u{200d}') {
+			self.combine_char(c);
+			return;
+		}
+		if self.cursor_x + width > self.canvas.width() {
+			// Wrap onto the next line
+			self.cursor_x = 0;
+			self.cursor_y += 1;
+		}
+		let (x, y) = (self.cursor_x as i32, self.cursor_y as i32);
+		self.put_cell(x, y, Symbol::Char(c));
+		if width == 2 {
+			self.put_cell(x + 1, y, Symbol::Continuation);
+		}
+		self.cursor_x += width;
+	}
+
+	fn put_cell(&mut self, x: i32, y: i32, symbol: Symbol) {
+		// Overwriting half of a wide glyph erases the other half
+		let style = Cell {
+			fg: self.fg,
+			bg: self.bg,
+			attrs: self.attrs,
+			underline_style: self.underline_style,
+			underline_color: self.underline_color,
+			link: self.link,
+			..Cell::null()
+		};
+		match self.canvas.cell(x, y).map(|cell| &cell.symbol) {
+			Some(Symbol::Continuation) if !symbol.is_continuation() => self.erase_cell(x - 1, y),
+			Some(old) if old.is_wide() => self.erase_cell(x + 1, y),
+			_ => {}
+		}
+		if let Some(cell) = self.canvas.cell_mut(x, y) {
+			*cell = Cell { symbol, ..style };
+		}
+	}
+
+	fn erase_cell(&mut self, x: i32, y: i32) {
+		if let Some(cell) = self.canvas.cell_mut(x, y) {
+			cell.symbol = Symbol::Char(' ');
+		}
+	}
+
+	fn previous_cell(&self) -> (i32, i32) {
+		let (x, y) = (self.cursor_x as i32 - 1, self.cursor_y as i32);
+		match self.canvas.cell(x, y) {
+			Some(cell) if cell.symbol.is_continuation() => (x - 1, y),
+			_ => (x, y),
+		}
+	}
+
+	fn previous_symbol(&self) -> Symbol {
+		let (x, y) = self.previous_cell();
+		self.canvas
+			.cell(x, y)
+			.map(|cell| cell.symbol.clone())
+			.unwrap_or_default()
+	}
+
+	// Zero width chars like combining accents, and anything after a zero width
+	// joiner, join the cluster in the previous cell
+	fn combine_char(&mut self, c: char) {
+		let (x, y) = self.previous_cell();
+		if let Some(cell) = self.canvas.cell_mut(x, y) {
+			let mut cluster = cell.symbol.to_string();
+			cluster.push(c);
+			cell.symbol = Symbol::Cluster(cluster.into());
+		}
+	}
+
+	fn read_byte(&mut self, byte: u8) {
+		match self.state {
+			ParseState::Ground => match byte {
+				ESC => self.state = ParseState::Escape,
+				b'\r' => self.cursor_x = 0,
+				b'\n' => self.cursor_y += 1,
+				_ => self.read_text_byte(byte),
+			},
+			ParseState::Escape => match byte {
+				b'[' => {
+					self.params.clear();
+					self.state = ParseState::Csi;
+				}
+				b']' => {
+					self.osc.clear();
+					self.state = ParseState::Osc;
+				}
+				// Not a sequence we understand
+				_ => self.state = ParseState::Ground,
+			},
+			ParseState::Osc => match byte {
+				// Terminated by BEL or ESC \
+				0x07 => {
+					self.state = ParseState::Ground;
+					self.run_os_command();
+				}
+				ESC => self.state = ParseState::OscEscape,
+				_ => self.osc.push(byte),
+			},
+			ParseState::OscEscape => {
+				self.state = ParseState::Ground;
+				self.run_os_command();
+			}
+			ParseState::Csi => match byte {
+				0x20..=0x3f => self.params.push(byte as char),
+				0x40..=0x7e => {
+					self.state = ParseState::Ground;
+					self.run_ctrl_sequence(byte as char);
+				}
+				_ => self.state = ParseState::Ground,
+			},
+		}
+	}
+
+	fn read_text_byte(&mut self, byte: u8) {
+		self.utf8.push(byte);
+		match std::str::from_utf8(&self.utf8) {
+			Ok(s) => {
+				let c = s.chars().next().unwrap_or('\0');
+				self.utf8.clear();
+				self.put_char(c);
+			}
+			Err(err) => {
+				if err.error_len().is_some() {
+					self.utf8.clear();
+					self.put_char(char::REPLACEMENT_CHARACTER);
+				}
+			}
+		}
+	}
+
+	fn run_os_command(&mut self) {
+		let osc = String::from_utf8_lossy(&self.osc);
+		// Hyperlinks are `8;params;url`, an empty url ends the link
+		if let Some((_params, url)) = osc.strip_prefix("8;").and_then(|link| link.split_once(';')) {
+			self.link = if url.is_empty() { None } else { Some(Link::new(url)) };
+		}
+	}
+
+	fn run_ctrl_sequence(&mut self, action: char) {
+		if self.params.starts_with('?') {
+			// Private modes (alt screen, mouse, cursor visibility) don't change the cells
+			return;
+		}
+		let fields: Vec<Vec<u32>> = self
+			.params
+			.split(';')
+			.map(|p| p.split(':').map(|sub| sub.parse().unwrap_or(0)).collect())
+			.collect();
+		let params: Vec<u32> = fields.iter().map(|field| field[0]).collect();
+
+		match action {
+			'H' | 'f' => {
+				let y = params.first().copied().unwrap_or(1).max(1) - 1;
+				let x = params.get(1).copied().unwrap_or(1).max(1) - 1;
+				self.cursor_x = x;
+				self.cursor_y = y;
+			}
+			'J' if params.first() == Some(&2) => self.canvas.clear(),
+			'm' => self.select_graphic_rendition(&params, &fields),
+			_ => {}
+		}
+	}
+
+	// The style goes back to the default too, so a plain 4 afterwards is a single underline
+	fn unset_underline(&mut self) {
+		self.attrs -= Attributes::UNDERLINE;
+		self.underline_style = UnderlineStyle::Single;
+	}
+
+	// `fields` has the ':' separated sub-parameters of each parameter
+	fn select_graphic_rendition(&mut self, params: &[u32], fields: &[Vec<u32>]) {
+		let mut i = 0;
+		while i < params.len() {
+			match params[i] {
+				0 => {
+					self.fg = Color::transparent();
+					self.bg = Color::transparent();
+					self.attrs = Attributes::empty();
+					self.underline_style = UnderlineStyle::Single;
+					self.underline_color = None;
+				}
+				1 => self.attrs |= Attributes::BOLD,
+				2 => self.attrs |= Attributes::DIM,
+				3 => self.attrs |= Attributes::ITALIC,
+				4 => match fields[i].get(1).copied() {
+					Some(0) => self.unset_underline(),
+					style => {
+						self.attrs |= Attributes::UNDERLINE;
+						self.underline_style = style.and_then(UnderlineStyle::from_sgr_code).unwrap_or_default();
+					}
+				},
+				5 => self.attrs |= Attributes::BLINK,
+				7 => self.attrs |= Attributes::REVERSE,
+				9 => self.attrs |= Attributes::STRIKETHROUGH,
+				22 => self.attrs -= Attributes::BOLD | Attributes::DIM,
+				23 => self.attrs -= Attributes::ITALIC,
+				24 => self.unset_underline(),
+				25 => self.attrs -= Attributes::BLINK,
+				27 => self.attrs -= Attributes::REVERSE,
+				29 => self.attrs -= Attributes::STRIKETHROUGH,
+				code @ (38 | 48 | 58) => {
+					let color = match params.get(i + 1) {
+						Some(2) if params.len() > i + 4 => {
+							let (r, g, b) = (params[i + 2], params[i + 3], params[i + 4]);
+							i += 4;
+							Color::rgb(r as u8, g as u8, b as u8)
+						}
+						Some(5) if params.len() > i + 2 => {
+							let n = params[i + 2];
+							i += 2;
+							Color::from_8bit_ansi(n as u8)
+						}
+						_ => break,
+					};
+					match code {
+						38 => self.fg = color,
+						48 => self.bg = color,
+						_ => self.underline_color = Some(color),
+					}
+				}
+				39 => self.fg = Color::transparent(),
+				49 => self.bg = Color::transparent(),
+				59 => self.underline_color = None,
+				code @ 30..=37 => self.fg = Color::from_8bit_ansi((code - 30) as u8),
+				code @ 40..=47 => self.bg = Color::from_8bit_ansi((code - 40) as u8),
+				code @ 90..=97 => self.fg = Color::from_8bit_ansi((code - 90 + 8) as u8),
+				code @ 100..=107 => self.bg = Color::from_8bit_ansi((code - 100 + 8) as u8),
+				_ => {}
+			}
+			i += 1;
+		}
+	}
+}
+
+impl Write for VirtualTerminal {
+	fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+		for &byte in buf {
+			self.read_byte(byte);
+		}
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> Result<(), io::Error> {
+		Ok(())
+	}
+}
+
+impl Backend for VirtualTerminal {
+	fn size(&self) -> (u32, u32) {
+		self.canvas.size()
+	}
+
+	fn move_to(&mut self, x: u32, y: u32) -> BackendResult {
+		self.escapes().move_to(x, y)
+	}
+
+	fn fg_color(&mut self, color: Color) -> BackendResult {
+		self.escapes().fg_color(color)
+	}
+
+	fn bg_color(&mut self, color: Color) -> BackendResult {
+		self.escapes().bg_color(color)
+	}
+
+	fn underline_color(&mut self, color: Color) -> BackendResult {
+		self.escapes().underline_color(color)
+	}
+
+	fn default_underline_color(&mut self) -> BackendResult {
+		self.escapes().default_underline_color()
+	}
+
+	fn reset(&mut self) -> BackendResult {
+		self.escapes().reset()
+	}
+
+	fn set_attributes(&mut self, attrs: Attributes) -> BackendResult {
+		self.escapes().set_attributes(attrs)
+	}
+
+	fn unset_attributes(&mut self, attrs: Attributes) -> BackendResult {
+		self.escapes().unset_attributes(attrs)
+	}
+
+	fn underline_style(&mut self, style: UnderlineStyle) -> BackendResult {
+		self.escapes().underline_style(style)
+	}
+
+	fn hyperlink(&mut self, link: Option<Link>) -> BackendResult {
+		self.escapes().hyperlink(link)
+	}
+
+	fn clear(&mut self) -> BackendResult {
+		self.escapes().clear()
+	}
+
+	fn write_char(&mut self, c: char) -> BackendResult {
+		self.escapes().write_char(c)
+	}
+
+	fn write_str(&mut self, s: &str) -> BackendResult {
+		self.escapes().write_str(s)
+	}
+
+	fn flush(&mut self) -> BackendResult {
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn run(writes: &[&[u8]]) -> VirtualTerminal {
+		let mut vt = VirtualTerminal::new(8, 3);
+		for bytes in writes {
+			vt.write_all(bytes).unwrap();
+		}
+		vt
+	}
+
+	fn text(c: char) -> Cell {
+		Cell {
+			symbol: Symbol::Char(c),
+			..Cell::null()
+		}
+	}
+
+	// Compares the top left cell after writing each input
+	fn assert_cells(cases: &[(&[u8], Cell)]) {
+		for (input, cell) in cases {
+			assert_eq!(
+				run(&[input]).cell(0, 0),
+				Some(cell),
+				"writing {:?}",
+				String::from_utf8_lossy(input)
+			);
+		}
+	}
+
+	#[test]
+	fn cursor() {
+		let vt = run(&[b"\x1b[2;3Hx"]);
+		assert_eq!(vt.cell(2, 1), Some(&text('x')));
+		assert_eq!(vt.cursor(), (3, 1));
+
+		let vt = run(&[b"\x1b[3;2fx\x1b[Hy"]);
+		assert_eq!(vt.cell(1, 2), Some(&text('x')));
+		assert_eq!(vt.cell(0, 0), Some(&text('y')));
+
+		let vt = run(&[b"ab\r\nc"]);
+		assert_eq!(vt.cell(1, 0), Some(&text('b')));
+		assert_eq!(vt.cell(0, 1), Some(&text('c')));
+
+		// Wraps at the right edge
+		let vt = run(&[b"abcdefghi"]);
+		assert_eq!(vt.cell(0, 1), Some(&text('i')));
+	}
+
+	#[test]
+	fn clear() {
+		let vt = run(&[b"\x1b[31mab\x1b[2J"]);
+		let cells = (0..3).flat_map(|y| (0..8).map(move |x| (x, y)));
+		assert!(cells.into_iter().all(|(x, y)| vt.cell(x, y).is_some_and(Cell::is_null)));
+		assert_eq!(vt.cursor(), (2, 0));
+		// Only 2J clears the screen
+		assert_eq!(run(&[b"ab\x1b[J"]).cell(0, 0), Some(&text('a')));
+	}
+
+	#[test]
+	fn colors() {
+		let fg = |color| Cell { fg: color, ..text('x') };
+		let bg = |color| Cell { bg: color, ..text('x') };
+		let underline = |color| Cell {
+			underline_color: Some(color),
+			..text('x')
+		};
+		assert_cells(&[
+			(b"\x1b[38;2;1;2;3mx", fg(Color::rgb(1, 2, 3))),
+			(b"\x1b[48;2;1;2;3mx", bg(Color::rgb(1, 2, 3))),
+			(b"\x1b[58;2;1;2;3mx", underline(Color::rgb(1, 2, 3))),
+			(b"\x1b[38;5;196mx", fg(Color::from_8bit_ansi(196))),
+			(b"\x1b[48;5;21mx", bg(Color::from_8bit_ansi(21))),
+			(b"\x1b[58;5;46mx", underline(Color::from_8bit_ansi(46))),
+			(b"\x1b[31mx", fg(Color::from_8bit_ansi(1))),
+			(b"\x1b[37mx", fg(Color::from_8bit_ansi(7))),
+			(b"\x1b[90mx", fg(Color::from_8bit_ansi(8))),
+			(b"\x1b[97mx", fg(Color::from_8bit_ansi(15))),
+			(b"\x1b[40mx", bg(Color::from_8bit_ansi(0))),
+			(b"\x1b[47mx", bg(Color::from_8bit_ansi(7))),
+			(b"\x1b[100mx", bg(Color::from_8bit_ansi(8))),
+			(b"\x1b[107mx", bg(Color::from_8bit_ansi(15))),
+			(b"\x1b[31;39mx", text('x')),
+			(b"\x1b[41;49mx", text('x')),
+			(b"\x1b[58;5;1;59mx", text('x')),
+			(b"\x1b[31;42;0mx", text('x')),
+			// Parameters after a color are still read
+			(
+				b"\x1b[38;5;2;1mx",
+				Cell {
+					attrs: Attributes::BOLD,
+					..fg(Color::from_8bit_ansi(2))
+				},
+			),
+		]);
+	}
+
+	#[test]
+	fn color_depth() {
+		let mut vt = VirtualTerminal::new(2, 1);
+		vt.set_color_depth(ColorDepth::Ansi16);
+		vt.fg_color(Color::rgb(250, 10, 10)).unwrap();
+		vt.write_char('x').unwrap();
+		assert_eq!(vt.cell(0, 0).unwrap().fg, Color::from_8bit_ansi(9));
+
+		vt.set_color_depth(ColorDepth::Mono);
+		vt.reset().unwrap();
+		vt.fg_color(Color::rgb(250, 10, 10)).unwrap();
+		vt.write_char('y').unwrap();
+		assert_eq!(vt.cell(1, 0), Some(&text('y')));
+	}
+
+	#[test]
+	fn styled_underlines() {
+		let mut vt = VirtualTerminal::new(2, 1);
+		vt.underline_style(UnderlineStyle::Curly).unwrap();
+		vt.underline_color(Color::rgb(1, 2, 3)).unwrap();
+		vt.write_char('x').unwrap();
+		let cell = vt.cell(0, 0).unwrap();
+		assert_eq!(cell.underline_style, UnderlineStyle::Curly);
+		assert_eq!(cell.underline_color, Some(Color::rgb(1, 2, 3)));
+
+		// Falls back to a plain single underline in the fg color
+		vt.set_styled_underlines(false);
+		vt.reset().unwrap();
+		vt.underline_style(UnderlineStyle::Curly).unwrap();
+		vt.underline_color(Color::rgb(1, 2, 3)).unwrap();
+		vt.write_char('y').unwrap();
+		let cell = vt.cell(1, 0).unwrap();
+		assert!(cell.attrs.contains(Attributes::UNDERLINE));
+		assert_eq!(cell.underline_style, UnderlineStyle::Single);
+		assert_eq!(cell.underline_color, None);
+	}
+
+	#[test]
+	fn attributes() {
+		let styled = |attrs, underline_style| Cell {
+			attrs,
+			underline_style,
+			..text('x')
+		};
+		assert_cells(&[
+			(
+				b"\x1b[1;3mx",
+				styled(Attributes::BOLD | Attributes::ITALIC, UnderlineStyle::Single),
+			),
+			(
+				b"\x1b[2;5;7;9mx",
+				styled(
+					Attributes::DIM | Attributes::BLINK | Attributes::REVERSE | Attributes::STRIKETHROUGH,
+					UnderlineStyle::Single,
+				),
+			),
+			(b"\x1b[1;2;22mx", text('x')),
+			(b"\x1b[3;23;5;25;7;27;9;29mx", text('x')),
+			(b"\x1b[4mx", styled(Attributes::UNDERLINE, UnderlineStyle::Single)),
+			(b"\x1b[4:3mx", styled(Attributes::UNDERLINE, UnderlineStyle::Curly)),
+			(b"\x1b[4:3;24mx", text('x')),
+			(b"\x1b[4:3m\x1b[4:0mx", text('x')),
+			(
+				b"\x1b[4:3m\x1b[24m\x1b[4mx",
+				styled(Attributes::UNDERLINE, UnderlineStyle::Single),
+			),
+			(b"\x1b[1;4:5;0mx", text('x')),
+		]);
+	}
+
+	#[test]
+	fn hyperlinks() {
+		let link = Some(Link::new("https://example.com"));
+		for input in [
+			&b"\x1b]8;;https://example.com\x07x\x1b]8;;\x07y"[..],
+			b"\x1b]8;;https://example.com\x1b\\x\x1b]8;;\x1b\\y",
+			b"\x1b]8;id=7;https://example.com\x1b\\x\x1b]8;;\x1b\\y",
+		] {
+			let vt = run(&[input]);
+			assert_eq!(
+				vt.cell(0, 0).unwrap().link,
+				link,
+				"writing {:?}",
+				String::from_utf8_lossy(input)
+			);
+			assert_eq!(vt.cell(1, 0), Some(&text('y')));
+		}
+	}
+
+	#[test]
+	fn utf8() {
+		assert_eq!(run(&["é€".as_bytes()]).line(0).trim_end(), "é€");
+		// Split across writes
+		assert_eq!(run(&[b"\xe2\x82", b"\xac"]).cell(0, 0), Some(&text('€')));
+		assert_eq!(run(&[b"\xf0\x9f", b"\x98", b"\x80"]).cell(0, 0).unwrap().symbol, '😀');
+		assert_eq!(run(&[b"\xffx"]).line(0).trim_end(), "\u{fffd}x");
+
+		let vt = run(&["世e\u{301}".as_bytes()]);
+		assert_eq!(vt.cell(1, 0).unwrap().symbol, Symbol::Continuation);
+		assert_eq!(vt.cell(2, 0).unwrap().symbol.to_string(), "e\u{301}");
+		assert_eq!(vt.cursor(), (3, 0));
+	}
+}